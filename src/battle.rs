@@ -0,0 +1,1992 @@
+// Headless combat rules shared by the `CombatPlugin` systems and by tests.
+// Nothing in here touches rendering, windows or input, so a whole fight can
+// be replayed from a seed on a machine without a GPU.
+
+use std::{collections::HashMap, fmt};
+
+use bevy::prelude::Component;
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::save_load::UnitJson;
+
+/// Name of a damage type as declared in the damage table, e.g. `"fire"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DamageType(pub String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Affinity {
+    Normal,
+    Weak,
+    Resist,
+    Immune,
+    Absorb,
+    Reflect,
+}
+
+impl Affinity {
+    fn default_multiplier(&self) -> f32 {
+        match self {
+            Affinity::Normal => 1.0,
+            Affinity::Weak => 2.0,
+            Affinity::Resist => 0.5,
+            Affinity::Immune => 0.0,
+            Affinity::Absorb => 1.0,
+            Affinity::Reflect => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DamageTypeDef {
+    pub code: String,
+    pub name: String,
+    #[serde(default)]
+    pub multipliers: HashMap<Affinity, f32>,
+}
+
+/// Every damage type the game knows about, how hard each affinity hits and
+/// how stats turn into damage. Loaded from `assets/combat/damage_types.json`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DamageTable {
+    pub types: Vec<DamageTypeDef>,
+    #[serde(default)]
+    pub multipliers: HashMap<Affinity, f32>,
+    #[serde(default)]
+    pub formula: DamageFormula,
+}
+
+/// How stats turn into damage, set under `"formula"` in the damage table.
+/// Chances are in `0.0..=1.0`. With every stat at its default a hit always
+/// lands for exactly the attacker's attack, as it did before stats existed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DamageFormula {
+    /// Damage is scaled by `1 / (1 + defense * defense_weight)`.
+    pub defense_weight: f32,
+    /// Damage varies by up to this fraction either way.
+    pub variance: f32,
+    pub crit_chance: f32,
+    pub crit_per_luck: f32,
+    pub crit_multiplier: f32,
+    /// Chance to hit when accuracy and evasion are equal.
+    pub hit_chance: f32,
+    /// Chance to hit gained per point of accuracy over the target's evasion.
+    pub hit_per_point: f32,
+    pub min_hit_chance: f32,
+}
+
+impl Default for DamageFormula {
+    fn default() -> Self {
+        DamageFormula {
+            defense_weight: 0.05,
+            variance: 0.0,
+            crit_chance: 0.0,
+            crit_per_luck: 0.01,
+            crit_multiplier: 1.5,
+            hit_chance: 1.0,
+            hit_per_point: 0.01,
+            min_hit_chance: 0.2,
+        }
+    }
+}
+
+impl DamageFormula {
+    pub fn hit_chance(&self, attacker: &StatBlock, target: &StatBlock) -> f32 {
+        let edge = attacker.accuracy as f32 - target.evasion as f32;
+        (self.hit_chance + edge * self.hit_per_point).clamp(self.min_hit_chance.min(1.0), 1.0)
+    }
+
+    pub fn crit_chance(&self, attacker: &StatBlock) -> f32 {
+        self.crit_chance + attacker.luck as f32 * self.crit_per_luck
+    }
+
+    /// Share of the damage that gets through `defense`.
+    pub fn mitigation(&self, defense: u32) -> f32 {
+        1.0 / (1.0 + defense as f32 * self.defense_weight.max(0.0))
+    }
+
+    pub fn roll(&self, attacker: &StatBlock, target: &StatBlock, rng: &mut BattleRng) -> HitRoll {
+        HitRoll {
+            hit: rng.chance(self.hit_chance(attacker, target)),
+            crit: rng.chance(self.crit_chance(attacker)),
+            spread: rng.spread(self.variance),
+        }
+    }
+
+    /// Like `roll`, but the skill's own accuracy has to land too. Evasion
+    /// only helps against skills aimed at foes.
+    pub fn roll_skill(
+        &self,
+        skill: &SkillDef,
+        user: &StatBlock,
+        target: &StatBlock,
+        rng: &mut BattleRng,
+    ) -> HitRoll {
+        let mut roll = self.roll(user, target, rng);
+        // Drawn even when the roll already missed, so every cast uses up the
+        // same numbers.
+        let lands = rng.chance(skill.accuracy);
+        roll.hit = (roll.hit || skill.target == TargetSide::Ally) && lands;
+        roll
+    }
+}
+
+/// The dice behind one hit, rolled up front by `DamageFormula::roll`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitRoll {
+    pub hit: bool,
+    pub crit: bool,
+    /// Variance applied to the damage, around 1.0.
+    pub spread: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownDamageType(pub String);
+
+impl fmt::Display for UnknownDamageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown damage type code '{}'", self.0)
+    }
+}
+
+impl std::error::Error for UnknownDamageType {}
+
+impl DamageTable {
+    pub fn parse(&self, code: &str) -> Result<DamageType, UnknownDamageType> {
+        self.types
+            .iter()
+            .find(|t| t.code == code)
+            .map(|t| DamageType(t.name.clone()))
+            .ok_or_else(|| UnknownDamageType(code.to_string()))
+    }
+
+    pub fn code(&self, dmg_type: &DamageType) -> Option<&str> {
+        self.types
+            .iter()
+            .find(|t| t.name == dmg_type.0)
+            .map(|t| t.code.as_str())
+    }
+
+    /// Per-type override first, then the table-wide value, then the built-in default.
+    pub fn multiplier(&self, dmg_type: &DamageType, affinity: Affinity) -> f32 {
+        self.types
+            .iter()
+            .find(|t| t.name == dmg_type.0)
+            .and_then(|t| t.multipliers.get(&affinity))
+            .or_else(|| self.multipliers.get(&affinity))
+            .copied()
+            .unwrap_or_else(|| affinity.default_multiplier())
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct AttackReceive {
+    pub hp: u32,
+    pub max_hp: u32,
+    pub mp: u32,
+    pub max_mp: u32,
+    pub affinities: HashMap<DamageType, Affinity>,
+    /// Set by the Defend action, cleared when the unit's next turn starts.
+    pub guarding: bool,
+    /// Scales damage taken, kept in sync with the unit's `StatusEffects`.
+    pub dmg_taken_multiplier: f32,
+}
+
+impl AttackReceive {
+    pub fn affinity(&self, dmg_type: &DamageType) -> Affinity {
+        self.affinities
+            .get(dmg_type)
+            .copied()
+            .unwrap_or(Affinity::Normal)
+    }
+
+    pub fn from_json(unit: &UnitJson, table: &DamageTable) -> Result<Self, UnknownDamageType> {
+        let lists = [
+            (&unit.weaknesses, Affinity::Weak),
+            (&unit.resistances, Affinity::Resist),
+            (&unit.immunities, Affinity::Immune),
+            (&unit.absorbs, Affinity::Absorb),
+            (&unit.reflects, Affinity::Reflect),
+        ];
+        let mut affinities = HashMap::new();
+        for (codes, affinity) in lists {
+            for code in codes.iter() {
+                affinities.insert(table.parse(code)?, affinity);
+            }
+        }
+        Ok(AttackReceive {
+            hp: unit.hp,
+            max_hp: unit.max_hp,
+            mp: unit.mp,
+            max_mp: unit.max_mp,
+            affinities,
+            guarding: false,
+            dmg_taken_multiplier: 1.0,
+        })
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct AttackSend {
+    pub used: bool,
+    pub dmg_type: DamageType,
+    /// `SkillDef::id`s the unit can use.
+    pub skills: Vec<String>,
+    /// Scales damage dealt, kept in sync with the unit's `StatusEffects`.
+    pub dmg_multiplier: f32,
+}
+
+impl AttackSend {
+    pub fn from_json(unit: &UnitJson, table: &DamageTable) -> Result<Self, UnknownDamageType> {
+        Ok(AttackSend {
+            used: false,
+            dmg_type: table.parse(&unit.dmg_type)?,
+            skills: unit.skills.clone(),
+            dmg_multiplier: 1.0,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stat {
+    Attack,
+    Defense,
+    Magic,
+    Speed,
+    Accuracy,
+    Evasion,
+    Luck,
+}
+
+impl Stat {
+    pub const ALL: [Stat; 7] = [
+        Stat::Attack,
+        Stat::Defense,
+        Stat::Magic,
+        Stat::Speed,
+        Stat::Accuracy,
+        Stat::Evasion,
+        Stat::Luck,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatBlock {
+    pub attack: u32,
+    pub defense: u32,
+    /// Scales the magical damage components of skills.
+    pub magic: u32,
+    /// Initiative gained per tick in `TurnMode::Initiative`.
+    pub speed: u32,
+    pub accuracy: u32,
+    pub evasion: u32,
+    pub luck: u32,
+}
+
+impl StatBlock {
+    /// A unit's `dmg` is its attack.
+    pub fn from_json(unit: &UnitJson) -> Self {
+        StatBlock {
+            attack: unit.dmg,
+            defense: unit.defense,
+            magic: unit.magic,
+            speed: unit.speed,
+            accuracy: unit.accuracy,
+            evasion: unit.evasion,
+            luck: unit.luck,
+        }
+    }
+
+    pub fn get(&self, stat: Stat) -> u32 {
+        match stat {
+            Stat::Attack => self.attack,
+            Stat::Defense => self.defense,
+            Stat::Magic => self.magic,
+            Stat::Speed => self.speed,
+            Stat::Accuracy => self.accuracy,
+            Stat::Evasion => self.evasion,
+            Stat::Luck => self.luck,
+        }
+    }
+
+    pub fn get_mut(&mut self, stat: Stat) -> &mut u32 {
+        match stat {
+            Stat::Attack => &mut self.attack,
+            Stat::Defense => &mut self.defense,
+            Stat::Magic => &mut self.magic,
+            Stat::Speed => &mut self.speed,
+            Stat::Accuracy => &mut self.accuracy,
+            Stat::Evasion => &mut self.evasion,
+            Stat::Luck => &mut self.luck,
+        }
+    }
+}
+
+/// A unit's stats: `base` from its unit file and `current` as scaled by its
+/// statuses, which is what combat reads. After changing `base` or the
+/// statuses call `recalculate`.
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub base: StatBlock,
+    pub current: StatBlock,
+}
+
+impl Stats {
+    pub fn from_json(unit: &UnitJson) -> Self {
+        let base = StatBlock::from_json(unit);
+        Stats {
+            base,
+            current: base,
+        }
+    }
+
+    pub fn recalculate(&mut self, statuses: &StatusEffects) {
+        for stat in Stat::ALL {
+            let flat = self.base.get(stat);
+            let scale: f32 = statuses
+                .active
+                .iter()
+                .filter_map(|s| s.def.stats.get(&stat))
+                .product();
+            *self.current.get_mut(stat) = (flat as f32 * scale).round() as u32;
+        }
+    }
+}
+
+/// Damage multiplier applied to hits on a guarding unit.
+pub const GUARD_MULTIPLIER: f32 = 0.5;
+
+/// A lingering effect as defined in `assets/combat/statuses.json`. Durations
+/// count turns of the affected unit.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StatusDef {
+    pub id: String,
+    pub name: String,
+    /// Short label drawn over the unit while the status lasts.
+    pub icon: String,
+    #[serde(default = "white")]
+    pub color: [f32; 3],
+    pub duration: u32,
+    /// The unit loses its turns while the status lasts.
+    #[serde(default)]
+    pub skip_turn: bool,
+    /// Hp lost at the start of each of the unit's turns.
+    #[serde(default)]
+    pub damage_per_turn: u32,
+    #[serde(default = "unscaled")]
+    pub dmg_multiplier: f32,
+    #[serde(default = "unscaled")]
+    pub dmg_taken_multiplier: f32,
+    /// Taking damage ends the status, like waking up from sleep.
+    #[serde(default)]
+    pub break_on_damage: bool,
+    /// Scales the unit's stats while the status lasts, e.g.
+    /// `{ "speed": 1.5 }`.
+    #[serde(default)]
+    pub stats: HashMap<Stat, f32>,
+}
+
+fn white() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn unscaled() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StatusTable {
+    pub statuses: Vec<StatusDef>,
+}
+
+impl StatusTable {
+    pub fn get(&self, id: &str) -> Option<&StatusDef> {
+        self.statuses.iter().find(|s| s.id == id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TurnStart {
+    /// The unit loses this turn.
+    pub skip: bool,
+    pub damage: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveStatus {
+    pub def: StatusDef,
+    pub turns_left: u32,
+}
+
+/// Statuses currently affecting a unit. After changing them call `sync` so
+/// the unit's damage multipliers follow.
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+pub struct StatusEffects {
+    pub active: Vec<ActiveStatus>,
+}
+
+impl StatusEffects {
+    /// Adds the status, or restarts its duration if the unit already has it.
+    pub fn inflict(&mut self, def: &StatusDef) {
+        match self.active.iter_mut().find(|s| s.def.id == def.id) {
+            Some(status) => status.turns_left = def.duration,
+            None => self.active.push(ActiveStatus {
+                def: def.clone(),
+                turns_left: def.duration,
+            }),
+        }
+    }
+
+    /// Removes the listed statuses and returns how many the unit had.
+    pub fn cure(&mut self, ids: &[String]) -> usize {
+        let before = self.active.len();
+        self.active.retain(|s| !ids.contains(&s.def.id));
+        before - self.active.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.active.clear();
+    }
+
+    /// Applies damage over time to `receive` and reports whether the turn is lost.
+    pub fn turn_start(&self, receive: &mut AttackReceive) -> TurnStart {
+        let damage: u32 = self.active.iter().map(|s| s.def.damage_per_turn).sum();
+        receive.hp = receive.hp.saturating_sub(damage);
+        TurnStart {
+            skip: self.active.iter().any(|s| s.def.skip_turn),
+            damage,
+        }
+    }
+
+    /// Counts down durations and drops the statuses that ran out.
+    pub fn turn_end(&mut self) {
+        for status in self.active.iter_mut() {
+            status.turns_left = status.turns_left.saturating_sub(1);
+        }
+        self.active.retain(|s| s.turns_left > 0);
+    }
+
+    /// Ends statuses that break on damage, if any damage was taken.
+    pub fn on_damage(&mut self, damage: u32) {
+        if damage > 0 {
+            self.active.retain(|s| !s.def.break_on_damage);
+        }
+    }
+
+    pub fn sync(&self, send: &mut AttackSend, receive: &mut AttackReceive) {
+        send.dmg_multiplier = self.active.iter().map(|s| s.def.dmg_multiplier).product();
+        receive.dmg_taken_multiplier = self
+            .active
+            .iter()
+            .map(|s| s.def.dmg_taken_multiplier)
+            .product();
+    }
+}
+
+/// Which side an item or skill is aimed at, relative to its user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetSide {
+    Ally,
+    Enemy,
+}
+
+impl TargetSide {
+    pub fn team(&self, user: Team) -> Team {
+        match (self, user) {
+            (TargetSide::Ally, team) => team,
+            (TargetSide::Enemy, Team::Player) => Team::Enemy,
+            (TargetSide::Enemy, Team::Enemy) => Team::Player,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetShape {
+    /// One unit picked by the user.
+    Single,
+    /// Every standing unit on the target side.
+    All,
+    /// The user itself, no target is picked.
+    User,
+}
+
+/// One hit of a skill. `power` scales the user's `dmg`, or its `magic` for a
+/// magical component, so `1.0` hits as hard as a plain attack.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DamageComponent {
+    pub power: f32,
+    pub dmg_type: String,
+    #[serde(default)]
+    pub magical: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SkillDef {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub mp_cost: u32,
+    pub target: TargetSide,
+    #[serde(default = "single")]
+    pub shape: TargetShape,
+    /// Chance in `0.0..=1.0` to land, rolled once per target.
+    #[serde(default = "always")]
+    pub accuracy: f32,
+    #[serde(default)]
+    pub damage: Vec<DamageComponent>,
+    /// Hp restored to each target after the damage components.
+    #[serde(default)]
+    pub heal: u32,
+    /// `StatusDef::id`s inflicted on every target the skill lands on.
+    #[serde(default)]
+    pub statuses: Vec<String>,
+}
+
+fn single() -> TargetShape {
+    TargetShape::Single
+}
+
+fn always() -> f32 {
+    1.0
+}
+
+/// Every skill units can learn, loaded from `assets/skills/skills.json`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SkillTable {
+    pub skills: Vec<SkillDef>,
+}
+
+impl SkillTable {
+    pub fn get(&self, id: &str) -> Option<&SkillDef> {
+        self.skills.iter().find(|s| s.id == id)
+    }
+}
+
+/// Result of a skill against one target. A reflected component shows up in
+/// `hits` with `hit_sender()` set, like a reflected attack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkillOutcome {
+    pub missed: bool,
+    pub hits: Vec<AttackOutcome>,
+    pub healed: u32,
+    pub hp_left: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ItemEffect {
+    Heal { amount: u32 },
+    Revive { hp: u32 },
+    Damage { amount: u32, dmg_type: String },
+    Cure { statuses: Vec<String> },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ItemDef {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub target: TargetSide,
+    pub effect: ItemEffect,
+}
+
+/// Every usable item, loaded from `assets/items/items.json`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ItemTable {
+    pub items: Vec<ItemDef>,
+}
+
+impl ItemTable {
+    pub fn get(&self, id: &str) -> Option<&ItemDef> {
+        self.items.iter().find(|i| i.id == id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemOutcome {
+    /// Hp restored or damage dealt, depending on the effect.
+    pub amount: u32,
+    /// How a damage item landed, `Normal` for every other effect.
+    pub affinity: Affinity,
+    pub multiplier: f32,
+    pub hp_left: u32,
+    pub killed: bool,
+    pub revived: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Team {
+    Player,
+    Enemy,
+}
+
+/// Seedable random source for everything that rolls dice during a fight.
+/// The same seed and the same inputs always replay the same battle.
+pub struct BattleRng(StdRng);
+
+impl BattleRng {
+    pub fn from_seed(seed: u64) -> Self {
+        Self(StdRng::seed_from_u64(seed))
+    }
+
+    pub fn from_entropy() -> Self {
+        Self(StdRng::from_entropy())
+    }
+
+    pub fn choose<I: Iterator>(&mut self, candidates: I) -> Option<I::Item> {
+        candidates.choose(&mut self.0)
+    }
+
+    /// True with probability `p`, clamped to `0.0..=1.0`.
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.0.gen_bool(p.clamp(0.0, 1.0) as f64)
+    }
+
+    /// A factor in `1.0 - variance..=1.0 + variance`.
+    pub fn spread(&mut self, variance: f32) -> f32 {
+        let variance = variance.clamp(0.0, 1.0);
+        if variance == 0.0 {
+            return 1.0;
+        }
+        self.0.gen_range(1.0 - variance..=1.0 + variance)
+    }
+}
+
+impl Default for BattleRng {
+    fn default() -> Self {
+        Self::from_entropy()
+    }
+}
+
+/// Result of one unit hitting another. With `Affinity::Absorb` `final_dmg` is
+/// the amount healed; with `Affinity::Reflect` it was dealt to the attacker,
+/// and `hp_left`/`killed` describe the attacker instead of the target. A
+/// missed attack deals nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttackOutcome {
+    pub raw_dmg: u32,
+    pub affinity: Affinity,
+    pub multiplier: f32,
+    pub final_dmg: u32,
+    pub hp_left: u32,
+    pub killed: bool,
+    pub missed: bool,
+    pub crit: bool,
+}
+
+impl AttackOutcome {
+    pub fn hit_sender(&self) -> bool {
+        self.affinity == Affinity::Reflect
+    }
+
+    /// Hp lost by whoever was hit, 0 when the damage was absorbed.
+    pub fn damage_taken(&self) -> u32 {
+        match self.affinity {
+            Affinity::Absorb => 0,
+            _ => self.final_dmg,
+        }
+    }
+}
+
+/// Drops the unit's guard and runs its statuses' turn start hook. A unit
+/// that loses the turn, or is knocked out by damage over time, is marked as
+/// having acted and its statuses count down right away.
+pub fn start_turn(
+    send: &mut AttackSend,
+    receive: &mut AttackReceive,
+    statuses: &mut StatusEffects,
+) -> TurnStart {
+    receive.guarding = false;
+    let start = statuses.turn_start(receive);
+    if start.skip || receive.hp == 0 {
+        send.used = true;
+        statuses.turn_end();
+    }
+    start
+}
+
+/// Raises the unit's guard until its next turn. Defending uses up the turn.
+pub fn defend(send: &mut AttackSend, receive: &mut AttackReceive) {
+    receive.guarding = true;
+    send.used = true;
+}
+
+/// Applies `send`'s attack to `receive` as `roll` came out and marks the
+/// sender as having acted. `send_receive` is the attacker's own defensive
+/// side, hit on a reflect. Damage comes from the attacker's `attack` and is
+/// mitigated by the target's `defense`; defense, like guard, does not reduce
+/// absorbed or reflected damage.
+pub fn resolve_attack(
+    send: &mut AttackSend,
+    attacker: &StatBlock,
+    send_receive: &mut AttackReceive,
+    receive: &mut AttackReceive,
+    target: &StatBlock,
+    roll: HitRoll,
+    table: &DamageTable,
+) -> AttackOutcome {
+    send.used = true;
+    if !roll.hit {
+        return AttackOutcome {
+            raw_dmg: 0,
+            affinity: Affinity::Normal,
+            multiplier: 1.0,
+            final_dmg: 0,
+            hp_left: receive.hp,
+            killed: false,
+            missed: true,
+            crit: false,
+        };
+    }
+    let formula = &table.formula;
+    let mut raw = attacker.attack as f32 * send.dmg_multiplier * roll.spread;
+    if roll.crit {
+        raw *= formula.crit_multiplier;
+    }
+    let raw_dmg = raw.round() as u32;
+    let affinity = receive.affinity(&send.dmg_type);
+    let mut multiplier = table.multiplier(&send.dmg_type, affinity);
+    if !matches!(affinity, Affinity::Absorb | Affinity::Reflect) {
+        if receive.guarding {
+            multiplier *= GUARD_MULTIPLIER;
+        }
+        multiplier *= receive.dmg_taken_multiplier * formula.mitigation(target.defense);
+    }
+    let final_dmg = (raw_dmg as f32 * multiplier).round() as u32;
+    let hit = match affinity {
+        Affinity::Absorb => {
+            receive.hp = std::cmp::min(receive.max_hp, receive.hp + final_dmg);
+            receive
+        }
+        Affinity::Reflect => {
+            send_receive.hp = send_receive.hp.saturating_sub(final_dmg);
+            send_receive
+        }
+        _ => {
+            receive.hp = receive.hp.saturating_sub(final_dmg);
+            receive
+        }
+    };
+    AttackOutcome {
+        raw_dmg,
+        affinity,
+        multiplier,
+        final_dmg,
+        hp_left: hit.hp,
+        killed: hit.hp == 0,
+        missed: false,
+        crit: roll.crit,
+    }
+}
+
+fn heal(receive: &mut AttackReceive, amount: u32) -> u32 {
+    if receive.hp == 0 {
+        return 0;
+    }
+    let healed = std::cmp::min(amount, receive.max_hp - receive.hp);
+    receive.hp += healed;
+    healed
+}
+
+/// Pays the mp for `skill` and uses up the turn. Returns false, changing
+/// nothing, when the unit cannot afford it.
+pub fn spend_mp(skill: &SkillDef, send: &mut AttackSend, receive: &mut AttackReceive) -> bool {
+    if receive.mp < skill.mp_cost {
+        return false;
+    }
+    receive.mp -= skill.mp_cost;
+    send.used = true;
+    true
+}
+
+/// Applies `skill` to one target as `roll` came out, see
+/// `DamageFormula::roll_skill`. Every damage component is its own hit of the
+/// user's `attack * power`, or `magic * power` for magical ones, resolved
+/// like an attack so affinities, guard, defense and reflect apply per
+/// component; `user` takes reflected hits. A `None` target means the skill is
+/// aimed at the user, which only heals. Statuses are left to the caller.
+pub fn resolve_skill(
+    skill: &SkillDef,
+    user_send: &AttackSend,
+    user_stats: &StatBlock,
+    user: &mut AttackReceive,
+    target: Option<(&mut AttackReceive, &StatBlock)>,
+    roll: HitRoll,
+    table: &DamageTable,
+) -> SkillOutcome {
+    let hit = roll.hit;
+    let mut outcome = SkillOutcome {
+        missed: !hit,
+        hits: Vec::new(),
+        healed: 0,
+        hp_left: 0,
+    };
+    let (target, target_stats) = match target {
+        Some(target) => target,
+        None => {
+            if hit {
+                outcome.healed = heal(user, skill.heal);
+            }
+            outcome.hp_left = user.hp;
+            return outcome;
+        }
+    };
+    if hit {
+        for component in skill.damage.iter() {
+            if target.hp == 0 || user.hp == 0 {
+                break;
+            }
+            let dmg_type = match table.parse(&component.dmg_type) {
+                Ok(dmg_type) => dmg_type,
+                Err(_) => continue,
+            };
+            let stat = if component.magical {
+                user_stats.magic
+            } else {
+                user_stats.attack
+            };
+            let attacker = StatBlock {
+                attack: (stat as f32 * component.power).round() as u32,
+                ..*user_stats
+            };
+            let mut send = AttackSend {
+                used: false,
+                dmg_type,
+                skills: Vec::new(),
+                dmg_multiplier: user_send.dmg_multiplier,
+            };
+            outcome.hits.push(resolve_attack(
+                &mut send,
+                &attacker,
+                user,
+                target,
+                target_stats,
+                roll,
+                table,
+            ));
+        }
+        outcome.healed = heal(target, skill.heal);
+    }
+    outcome.hp_left = target.hp;
+    outcome
+}
+
+/// Applies `item` to `target` and uses up `user`'s turn. Returns `None`, with
+/// the turn untouched, when the item would do nothing: healing, curing or
+/// damaging a knocked out unit, reviving one that is still standing, or
+/// curing a status the target does not have. Items are never reflected; a
+/// reflecting target takes item damage normally. For cures `amount` is the
+/// number of statuses removed.
+pub fn use_item(
+    item: &ItemDef,
+    user: &mut AttackSend,
+    target: &mut AttackReceive,
+    target_statuses: &mut StatusEffects,
+    table: &DamageTable,
+) -> Option<ItemOutcome> {
+    let down = target.hp == 0;
+    let mut affinity = Affinity::Normal;
+    let mut multiplier = 1.0;
+    let (amount, revived) = match &item.effect {
+        ItemEffect::Heal { amount } if !down => (heal(target, *amount), false),
+        ItemEffect::Revive { hp } if down => {
+            target_statuses.clear();
+            target.hp = std::cmp::min(*hp, target.max_hp).max(1);
+            (target.hp, true)
+        }
+        ItemEffect::Damage { amount, dmg_type } if !down => {
+            let dmg_type = table.parse(dmg_type).ok()?;
+            affinity = match target.affinity(&dmg_type) {
+                Affinity::Reflect => Affinity::Normal,
+                affinity => affinity,
+            };
+            multiplier = table.multiplier(&dmg_type, affinity);
+            let dmg = (*amount as f32 * multiplier).round() as u32;
+            if affinity == Affinity::Absorb {
+                target.hp = std::cmp::min(target.max_hp, target.hp + dmg);
+            } else {
+                target.hp = target.hp.saturating_sub(dmg);
+            }
+            (dmg, false)
+        }
+        ItemEffect::Cure { statuses } if !down => match target_statuses.cure(statuses) {
+            0 => return None,
+            cured => (cured as u32, false),
+        },
+        _ => return None,
+    };
+    user.used = true;
+    Some(ItemOutcome {
+        amount,
+        affinity,
+        multiplier,
+        hp_left: target.hp,
+        killed: !down && target.hp == 0,
+        revived,
+    })
+}
+
+/// Charge a unit needs to take a turn in the initiative queue.
+pub const INITIATIVE_THRESHOLD: u32 = 100;
+
+#[derive(Debug, Clone)]
+pub struct InitiativeEntry<K> {
+    pub unit: K,
+    pub speed: u32,
+    pub charge: u32,
+}
+
+/// Charge time turn order: every tick each unit gains its speed in charge,
+/// and whoever reaches `INITIATIVE_THRESHOLD` first acts and pays it back.
+/// Faster units therefore act more often. `K` is whatever identifies a unit,
+/// e.g. an `Entity` in the ECS.
+#[derive(Debug, Clone)]
+pub struct Initiative<K> {
+    pub entries: Vec<InitiativeEntry<K>>,
+}
+
+impl<K> Default for Initiative<K> {
+    fn default() -> Self {
+        Initiative {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<K: Copy + PartialEq> Initiative<K> {
+    /// Brings the queue in line with the units still standing and their
+    /// current speed. New units start without charge.
+    pub fn sync(&mut self, units: impl Iterator<Item = (K, u32)>) {
+        let units: Vec<(K, u32)> = units.collect();
+        self.entries
+            .retain(|e| units.iter().any(|(unit, _speed)| *unit == e.unit));
+        for (unit, speed) in units {
+            match self.entries.iter_mut().find(|e| e.unit == unit) {
+                Some(entry) => entry.speed = speed,
+                None => self.entries.push(InitiativeEntry {
+                    unit,
+                    speed,
+                    charge: 0,
+                }),
+            }
+        }
+    }
+
+    /// The unit whose turn is next. Ties go to the higher charge, then to
+    /// the unit that joined the queue first.
+    pub fn next(&mut self) -> Option<K> {
+        if self.entries.iter().all(|e| e.speed == 0) {
+            return None;
+        }
+        loop {
+            let ready = self
+                .entries
+                .iter_mut()
+                .filter(|e| e.charge >= INITIATIVE_THRESHOLD)
+                .fold(None, |best: Option<&mut InitiativeEntry<K>>, e| match best {
+                    Some(best) if best.charge >= e.charge => Some(best),
+                    _ => Some(e),
+                });
+            if let Some(entry) = ready {
+                entry.charge -= INITIATIVE_THRESHOLD;
+                return Some(entry.unit);
+            }
+            for entry in self.entries.iter_mut() {
+                entry.charge += entry.speed;
+            }
+        }
+    }
+
+    /// The next `count` turns, leaving the queue untouched.
+    pub fn preview(&self, count: usize) -> Vec<K> {
+        let mut queue = self.clone();
+        (0..count).map_while(|_| queue.next()).collect()
+    }
+}
+
+/// One step of a scripted enemy's rotation.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AiStep {
+    Attack,
+    Defend,
+    /// Uses the skill with this `SkillDef::id`, or attacks if it cannot pay
+    /// for it.
+    Skill {
+        id: String,
+    },
+}
+
+/// How an enemy picks its action, set per unit in the encounter file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AiProfile {
+    /// Attacks a random opponent.
+    #[default]
+    Random,
+    /// Attacks the opponent with the least hp left.
+    Aggressive,
+    /// Attacks an opponent weak to its damage type, any opponent if none is.
+    ExploitWeakness,
+    /// Heals the most hurt ally below `threshold` of its max hp with a
+    /// healing skill, guards when below `threshold` itself, attacks a random
+    /// opponent otherwise.
+    Defensive {
+        #[serde(default = "half")]
+        threshold: f32,
+    },
+    /// Plays `sequence` in order and starts over at the end.
+    Scripted { sequence: Vec<AiStep> },
+}
+
+fn half() -> f32 {
+    0.5
+}
+
+/// An enemy's `AiProfile` and how far it got through a scripted sequence.
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+pub struct EnemyAi {
+    pub profile: AiProfile,
+    step: usize,
+}
+
+impl EnemyAi {
+    pub fn new(profile: AiProfile) -> Self {
+        EnemyAi { profile, step: 0 }
+    }
+
+    /// The action `units[user]` takes on its turn. Indices in the returned
+    /// action point into `units` and `skills.skills`. `None` when nobody on
+    /// the other side is left standing.
+    pub fn decide(
+        &mut self,
+        user: usize,
+        units: &[Combatant],
+        skills: &SkillTable,
+        rng: &mut BattleRng,
+    ) -> Option<Action> {
+        let me = units.get(user)?;
+        let foes: Vec<usize> = standing(units, |u| u.team != me.team).collect();
+        if foes.is_empty() {
+            return None;
+        }
+        let attack = |receive| Action::Attack {
+            send: user,
+            receive,
+        };
+        let random_attack = |rng: &mut BattleRng| rng.choose(foes.iter().copied()).map(attack);
+        match &self.profile {
+            AiProfile::Random => random_attack(rng),
+            AiProfile::Aggressive => foes
+                .iter()
+                .copied()
+                .min_by_key(|&i| units[i].receive.hp)
+                .map(attack),
+            AiProfile::ExploitWeakness => {
+                let weak = foes
+                    .iter()
+                    .copied()
+                    .filter(|&i| units[i].receive.affinity(&me.send.dmg_type) == Affinity::Weak);
+                match rng.choose(weak) {
+                    Some(receive) => Some(attack(receive)),
+                    None => random_attack(rng),
+                }
+            }
+            AiProfile::Defensive { threshold } => {
+                let hurt =
+                    most_hurt(units, me.team).filter(|&i| hp_fraction(&units[i]) < *threshold);
+                let heal = me
+                    .send
+                    .skills
+                    .iter()
+                    .filter_map(|id| skills.skills.iter().position(|s| &s.id == id))
+                    .find(|&i| {
+                        let skill = &skills.skills[i];
+                        skill.heal > 0
+                            && skill.target == TargetSide::Ally
+                            && skill.mp_cost <= me.receive.mp
+                            && (skill.shape != TargetShape::User || hurt == Some(user))
+                    });
+                if let (Some(target), Some(skill)) = (hurt, heal) {
+                    return Some(Action::UseSkill {
+                        user,
+                        target,
+                        skill,
+                    });
+                }
+                if hp_fraction(me) < *threshold {
+                    return Some(Action::Defend { unit: user });
+                }
+                random_attack(rng)
+            }
+            AiProfile::Scripted { sequence } => {
+                if sequence.is_empty() {
+                    return random_attack(rng);
+                }
+                let step = &sequence[self.step % sequence.len()];
+                self.step = (self.step + 1) % sequence.len();
+                match step {
+                    AiStep::Attack => random_attack(rng),
+                    AiStep::Defend => Some(Action::Defend { unit: user }),
+                    AiStep::Skill { id } => {
+                        let skill = skills
+                            .skills
+                            .iter()
+                            .position(|s| &s.id == id)
+                            .filter(|&i| skills.skills[i].mp_cost <= me.receive.mp);
+                        let skill = match skill {
+                            Some(skill) => skill,
+                            None => return random_attack(rng),
+                        };
+                        let target = match skills.skills[skill].target {
+                            TargetSide::Ally => most_hurt(units, me.team).unwrap_or(user),
+                            TargetSide::Enemy => rng.choose(foes.iter().copied())?,
+                        };
+                        Some(Action::UseSkill {
+                            user,
+                            target,
+                            skill,
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn standing<'a>(
+    units: &'a [Combatant],
+    filter: impl Fn(&Combatant) -> bool + 'a,
+) -> impl Iterator<Item = usize> + 'a {
+    units
+        .iter()
+        .enumerate()
+        .filter(move |(_i, u)| u.is_alive() && filter(u))
+        .map(|(i, _u)| i)
+}
+
+fn hp_fraction(unit: &Combatant) -> f32 {
+    unit.receive.hp as f32 / unit.receive.max_hp.max(1) as f32
+}
+
+/// The standing unit on `team` with the smallest share of its max hp left.
+fn most_hurt(units: &[Combatant], team: Team) -> Option<usize> {
+    standing(units, |u| u.team == team)
+        .min_by(|&a, &b| hp_fraction(&units[a]).total_cmp(&hp_fraction(&units[b])))
+}
+
+#[derive(Debug, Clone)]
+pub struct Combatant {
+    pub team: Team,
+    pub send: AttackSend,
+    pub receive: AttackReceive,
+    pub statuses: StatusEffects,
+    pub stats: Stats,
+    /// Only used for enemies.
+    pub ai: EnemyAi,
+}
+
+impl Combatant {
+    pub fn from_json(
+        unit: &UnitJson,
+        team: Team,
+        table: &DamageTable,
+    ) -> Result<Self, UnknownDamageType> {
+        Ok(Combatant {
+            team,
+            send: AttackSend::from_json(unit, table)?,
+            receive: AttackReceive::from_json(unit, table)?,
+            statuses: StatusEffects::default(),
+            stats: Stats::from_json(unit),
+            ai: EnemyAi::default(),
+        })
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.receive.hp > 0
+    }
+
+    /// Knocked out units lose their statuses, the rest get their damage
+    /// multipliers and stats brought up to date.
+    fn refresh_statuses(&mut self) {
+        if !self.is_alive() {
+            self.statuses.clear();
+        }
+        self.statuses.sync(&mut self.send, &mut self.receive);
+        self.stats.recalculate(&self.statuses);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Attack { send: usize, receive: usize },
+    Defend { unit: usize },
+    UseItem { user: usize, target: usize, item: usize },
+    /// `target` picks the side for `TargetShape::All` and is ignored for
+    /// `TargetShape::User`.
+    UseSkill { user: usize, target: usize, skill: usize },
+}
+
+/// Record of a resolved action, indices point into `Battle::units`.
+/// `attack` is `None` for actions that deal no damage, `skill` lists each
+/// unit a skill was aimed at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionOutcome {
+    pub action: Action,
+    pub attack: Option<AttackOutcome>,
+    pub item: Option<ItemOutcome>,
+    pub skill: Vec<(usize, SkillOutcome)>,
+}
+
+/// The tables a fight is played by.
+#[derive(Clone, Copy)]
+pub struct Rules<'a> {
+    pub damage: &'a DamageTable,
+    pub items: &'a ItemTable,
+    pub skills: &'a SkillTable,
+    pub statuses: &'a StatusTable,
+}
+
+/// A fight held in plain data. Units are addressed by their index in
+/// `units`; dead units stay in place so indices remain stable. Items and
+/// skills are addressed by their index in `rules.items.items` and
+/// `rules.skills.skills`. The `CombatPlugin` builds one from the units on
+/// the field whenever a turn starts or an action resolves, and copies the
+/// units back afterwards.
+pub struct Battle<'a> {
+    pub units: Vec<Combatant>,
+    pub rules: Rules<'a>,
+    pub rng: &'a mut BattleRng,
+}
+
+impl<'a> Battle<'a> {
+    pub fn new(units: Vec<Combatant>, rules: Rules<'a>, rng: &'a mut BattleRng) -> Self {
+        Battle { units, rules, rng }
+    }
+
+    pub fn living(&self, team: Team) -> impl Iterator<Item = usize> + '_ {
+        self.units
+            .iter()
+            .enumerate()
+            .filter(move |(_i, u)| u.team == team && u.is_alive())
+            .map(|(i, _u)| i)
+    }
+
+    /// Starts the unit's turn, see `start_turn`.
+    pub fn start_turn(&mut self, unit: usize) -> TurnStart {
+        let combatant = &mut self.units[unit];
+        let start = start_turn(
+            &mut combatant.send,
+            &mut combatant.receive,
+            &mut combatant.statuses,
+        );
+        combatant.refresh_statuses();
+        start
+    }
+
+    /// Counts down the unit's statuses once its action is resolved.
+    pub fn end_turn(&mut self, unit: usize) {
+        let combatant = &mut self.units[unit];
+        combatant.statuses.turn_end();
+        combatant.refresh_statuses();
+    }
+
+    /// What enemy `send` does on its turn, as picked by its `EnemyAi`.
+    pub fn enemy_action(&mut self, send: usize) -> Option<Action> {
+        let mut ai = self.units.get(send)?.ai.clone();
+        let action = ai.decide(send, &self.units, self.rules.skills, self.rng);
+        self.units[send].ai = ai;
+        action
+    }
+
+    /// Returns `None` when either side of the action is missing or dead.
+    pub fn resolve_action(&mut self, action: Action) -> Option<ActionOutcome> {
+        match action {
+            Action::Attack { send, receive } => {
+                if send == receive
+                    || !self.units.get(send)?.is_alive()
+                    || !self.units.get(receive)?.is_alive()
+                {
+                    return None;
+                }
+                let mut sender = self.units[send].clone();
+                let roll = self.rules.damage.formula.roll(
+                    &sender.stats.current,
+                    &self.units[receive].stats.current,
+                    self.rng,
+                );
+                let target = &mut self.units[receive];
+                let attack = resolve_attack(
+                    &mut sender.send,
+                    &sender.stats.current,
+                    &mut sender.receive,
+                    &mut target.receive,
+                    &target.stats.current,
+                    roll,
+                    self.rules.damage,
+                );
+                let hit = if attack.hit_sender() {
+                    &mut sender
+                } else {
+                    &mut self.units[receive]
+                };
+                hit.statuses.on_damage(attack.damage_taken());
+                hit.refresh_statuses();
+                self.units[send] = sender;
+                Some(ActionOutcome {
+                    action,
+                    attack: Some(attack),
+                    item: None,
+                    skill: Vec::new(),
+                })
+            }
+            Action::Defend { unit } => {
+                let unit = self.units.get_mut(unit)?;
+                if !unit.is_alive() {
+                    return None;
+                }
+                defend(&mut unit.send, &mut unit.receive);
+                Some(ActionOutcome {
+                    action,
+                    attack: None,
+                    item: None,
+                    skill: Vec::new(),
+                })
+            }
+            Action::UseItem { user, target, item } => {
+                let def = self.rules.items.items.get(item)?;
+                if !self.units.get(user)?.is_alive() {
+                    return None;
+                }
+                self.units.get(target)?;
+                let mut sender = self.units[user].send.clone();
+                let receiver = &mut self.units[target];
+                let outcome = use_item(
+                    def,
+                    &mut sender,
+                    &mut receiver.receive,
+                    &mut receiver.statuses,
+                    self.rules.damage,
+                )?;
+                receiver.refresh_statuses();
+                self.units[user].send = sender;
+                Some(ActionOutcome {
+                    action,
+                    attack: None,
+                    item: Some(outcome),
+                    skill: Vec::new(),
+                })
+            }
+            Action::UseSkill {
+                user,
+                target,
+                skill,
+            } => {
+                let def = self.rules.skills.skills.get(skill)?;
+                if !self.units.get(user)?.is_alive() {
+                    return None;
+                }
+                let targets: Vec<usize> = match def.shape {
+                    TargetShape::Single => {
+                        if !self.units.get(target)?.is_alive() {
+                            return None;
+                        }
+                        vec![target]
+                    }
+                    TargetShape::All => {
+                        let team = self.units.get(target)?.team;
+                        self.living(team).collect()
+                    }
+                    TargetShape::User => vec![user],
+                };
+                let mut sender = self.units[user].clone();
+                if !spend_mp(def, &mut sender.send, &mut sender.receive) {
+                    return None;
+                }
+                let mut outcomes = Vec::new();
+                for t in targets {
+                    let roll = self.rules.damage.formula.roll_skill(
+                        def,
+                        &sender.stats.current,
+                        &self.units[t].stats.current,
+                        self.rng,
+                    );
+                    let target = if t == user {
+                        None
+                    } else {
+                        let unit = &mut self.units[t];
+                        Some((&mut unit.receive, &unit.stats.current))
+                    };
+                    let outcome = resolve_skill(
+                        def,
+                        &sender.send,
+                        &sender.stats.current,
+                        &mut sender.receive,
+                        target,
+                        roll,
+                        self.rules.damage,
+                    );
+                    for h in outcome.hits.iter().filter(|h| h.hit_sender()) {
+                        sender.statuses.on_damage(h.damage_taken());
+                    }
+                    sender.refresh_statuses();
+                    let hit_unit = if t == user {
+                        &mut sender
+                    } else {
+                        &mut self.units[t]
+                    };
+                    for h in outcome.hits.iter().filter(|h| !h.hit_sender()) {
+                        hit_unit.statuses.on_damage(h.damage_taken());
+                    }
+                    if !outcome.missed && hit_unit.is_alive() {
+                        for id in def.statuses.iter() {
+                            if let Some(status) = self.rules.statuses.get(id) {
+                                hit_unit.statuses.inflict(status);
+                            }
+                        }
+                    }
+                    hit_unit.refresh_statuses();
+                    outcomes.push((t, outcome));
+                    if !sender.is_alive() {
+                        break;
+                    }
+                }
+                self.units[user] = sender;
+                Some(ActionOutcome {
+                    action,
+                    attack: None,
+                    item: None,
+                    skill: outcomes,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const HIT: HitRoll = HitRoll {
+        hit: true,
+        crit: false,
+        spread: 1.0,
+    };
+
+    fn damage_table() -> DamageTable {
+        serde_json::from_value(json!({
+            "types": [
+                { "code": "S", "name": "slashing" },
+                { "code": "F", "name": "fire" }
+            ]
+        }))
+        .unwrap()
+    }
+
+    /// A unit with 100 hp hitting for 10 slashing, with `fields` on top.
+    fn unit(team: Team, fields: serde_json::Value) -> Combatant {
+        let mut unit = json!({
+            "name": "unit",
+            "sprite": "unit",
+            "max_hp": 100,
+            "hp": 100,
+            "dmg": 10,
+            "dmg_type": "S",
+            "weaknesses": [],
+            "resistances": []
+        });
+        if let (Some(unit), Some(fields)) = (unit.as_object_mut(), fields.as_object()) {
+            unit.extend(fields.clone());
+        }
+        let unit: UnitJson = serde_json::from_value(unit).unwrap();
+        Combatant::from_json(&unit, team, &damage_table()).unwrap()
+    }
+
+    fn attack(attacker: &mut Combatant, target: &mut Combatant) -> AttackOutcome {
+        resolve_attack(
+            &mut attacker.send,
+            &attacker.stats.current,
+            &mut attacker.receive,
+            &mut target.receive,
+            &target.stats.current,
+            HIT,
+            &damage_table(),
+        )
+    }
+
+    #[test]
+    fn weak_target_takes_double_damage() {
+        let mut attacker = unit(Team::Player, json!({}));
+        let mut target = unit(Team::Enemy, json!({ "weaknesses": ["S"] }));
+        let outcome = attack(&mut attacker, &mut target);
+        assert_eq!(outcome.affinity, Affinity::Weak);
+        assert_eq!(outcome.final_dmg, 20);
+        assert_eq!(target.receive.hp, 80);
+        assert!(attacker.send.used);
+    }
+
+    #[test]
+    fn resisting_target_takes_half_damage() {
+        let mut attacker = unit(Team::Player, json!({}));
+        let mut target = unit(Team::Enemy, json!({ "resistances": ["S"] }));
+        let outcome = attack(&mut attacker, &mut target);
+        assert_eq!(outcome.affinity, Affinity::Resist);
+        assert_eq!(outcome.final_dmg, 5);
+        assert_eq!(target.receive.hp, 95);
+    }
+
+    #[test]
+    fn absorbing_target_is_healed() {
+        let mut attacker = unit(Team::Player, json!({}));
+        let mut target = unit(Team::Enemy, json!({ "hp": 50, "absorbs": ["S"] }));
+        let outcome = attack(&mut attacker, &mut target);
+        assert_eq!(outcome.affinity, Affinity::Absorb);
+        assert_eq!(outcome.damage_taken(), 0);
+        assert_eq!(target.receive.hp, 60);
+    }
+
+    #[test]
+    fn reflecting_target_hits_the_attacker() {
+        let mut attacker = unit(Team::Player, json!({}));
+        let mut target = unit(Team::Enemy, json!({ "reflects": ["S"] }));
+        let outcome = attack(&mut attacker, &mut target);
+        assert!(outcome.hit_sender());
+        assert_eq!(outcome.hp_left, 90);
+        assert_eq!(attacker.receive.hp, 90);
+        assert_eq!(target.receive.hp, 100);
+    }
+
+    #[test]
+    fn guard_halves_damage_until_the_next_turn() {
+        let mut attacker = unit(Team::Player, json!({}));
+        let mut target = unit(Team::Enemy, json!({}));
+        defend(&mut target.send, &mut target.receive);
+        assert_eq!(attack(&mut attacker, &mut target).final_dmg, 5);
+
+        start_turn(&mut target.send, &mut target.receive, &mut target.statuses);
+        assert_eq!(attack(&mut attacker, &mut target).final_dmg, 10);
+    }
+
+    #[test]
+    fn revive_only_works_on_knocked_out_units() {
+        let damage = damage_table();
+        let items: ItemTable = serde_json::from_value(json!({
+            "items": [{
+                "id": "phoenix_down",
+                "name": "Phoenix Down",
+                "target": "ally",
+                "effect": { "kind": "revive", "hp": 25 }
+            }]
+        }))
+        .unwrap();
+        let skills = SkillTable::default();
+        let statuses = StatusTable::default();
+        let rules = Rules {
+            damage: &damage,
+            items: &items,
+            skills: &skills,
+            statuses: &statuses,
+        };
+        let mut rng = BattleRng::from_seed(1);
+        let units = vec![
+            unit(Team::Player, json!({})),
+            unit(Team::Player, json!({ "hp": 0 })),
+        ];
+        let mut battle = Battle::new(units, rules, &mut rng);
+        let revive = |target| Action::UseItem {
+            user: 0,
+            target,
+            item: 0,
+        };
+
+        assert_eq!(battle.resolve_action(revive(0)), None);
+        assert!(!battle.units[0].send.used);
+
+        let outcome = battle.resolve_action(revive(1)).unwrap();
+        assert_eq!(outcome.attack, None);
+        let item = outcome.item.unwrap();
+        assert!(item.revived);
+        assert_eq!(item.hp_left, 25);
+        assert!(battle.units[1].is_alive());
+        assert!(battle.units[0].send.used);
+    }
+
+    #[test]
+    fn skills_cost_mp_and_fail_without_it() {
+        let damage = damage_table();
+        let items = ItemTable::default();
+        let skills: SkillTable = serde_json::from_value(json!({
+            "skills": [{
+                "id": "cleave",
+                "name": "Cleave",
+                "mp_cost": 4,
+                "target": "enemy",
+                "shape": "all",
+                "damage": [{ "power": 2.0, "dmg_type": "S" }]
+            }]
+        }))
+        .unwrap();
+        let statuses = StatusTable::default();
+        let rules = Rules {
+            damage: &damage,
+            items: &items,
+            skills: &skills,
+            statuses: &statuses,
+        };
+        let mut rng = BattleRng::from_seed(1);
+        let units = vec![
+            unit(Team::Player, json!({ "max_mp": 6, "mp": 6, "skills": ["cleave"] })),
+            unit(Team::Enemy, json!({})),
+            unit(Team::Enemy, json!({})),
+        ];
+        let mut battle = Battle::new(units, rules, &mut rng);
+        let cleave = Action::UseSkill {
+            user: 0,
+            target: 1,
+            skill: 0,
+        };
+
+        let outcome = battle.resolve_action(cleave).unwrap();
+        let targets: Vec<usize> = outcome.skill.iter().map(|(t, _s)| *t).collect();
+        assert_eq!(targets, vec![1, 2]);
+        assert_eq!(battle.units[0].receive.mp, 2);
+        assert_eq!(battle.units[1].receive.hp, 80);
+        assert_eq!(battle.units[2].receive.hp, 80);
+
+        assert_eq!(battle.resolve_action(cleave), None);
+        assert_eq!(battle.units[0].receive.mp, 2);
+    }
+
+    #[test]
+    fn sleep_costs_turns_until_damage_breaks_it() {
+        let damage = damage_table();
+        let items = ItemTable::default();
+        let skills = SkillTable::default();
+        let statuses: StatusTable = serde_json::from_value(json!({
+            "statuses": [{
+                "id": "sleep",
+                "name": "Sleep",
+                "icon": "ZZZ",
+                "duration": 3,
+                "skip_turn": true,
+                "break_on_damage": true
+            }]
+        }))
+        .unwrap();
+        let rules = Rules {
+            damage: &damage,
+            items: &items,
+            skills: &skills,
+            statuses: &statuses,
+        };
+        let mut rng = BattleRng::from_seed(1);
+        let units = vec![unit(Team::Player, json!({})), unit(Team::Enemy, json!({}))];
+        let mut battle = Battle::new(units, rules, &mut rng);
+        battle.units[0].statuses.inflict(&statuses.statuses[0]);
+
+        assert!(battle.start_turn(0).skip);
+        assert!(battle.units[0].send.used);
+        assert_eq!(battle.units[0].statuses.active[0].turns_left, 2);
+
+        battle.units[0].send.used = false;
+        battle
+            .resolve_action(Action::Attack {
+                send: 1,
+                receive: 0,
+            })
+            .unwrap();
+        assert!(battle.units[0].statuses.active.is_empty());
+        assert!(!battle.start_turn(0).skip);
+    }
+
+    #[test]
+    fn faster_units_act_more_often() {
+        let mut queue = Initiative::default();
+        queue.sync([(0, 10), (1, 20)].into_iter());
+        assert_eq!(queue.preview(6), vec![1, 0, 1, 1, 0, 1]);
+        assert_eq!(queue.next(), Some(1));
+
+        queue.sync([(0, 10)].into_iter());
+        assert_eq!(queue.preview(2), vec![0, 0]);
+    }
+
+    #[test]
+    fn skill_rolls_draw_accuracy_even_after_a_miss() {
+        let mut damage = damage_table();
+        damage.formula.hit_chance = 0.5;
+        let skill: SkillDef = serde_json::from_value(json!({
+            "id": "jab",
+            "name": "Jab",
+            "target": "enemy",
+            "accuracy": 0.9
+        }))
+        .unwrap();
+        let stats = StatBlock::default();
+        let mut missed = false;
+        for seed in 0..16 {
+            let mut rng = BattleRng::from_seed(seed);
+            let mut expected = BattleRng::from_seed(seed);
+            damage.formula.roll_skill(&skill, &stats, &stats, &mut rng);
+            missed |= !damage.formula.roll(&stats, &stats, &mut expected).hit;
+            expected.chance(skill.accuracy);
+            assert_eq!(rng.spread(1.0), expected.spread(1.0));
+        }
+        assert!(missed);
+    }
+
+    /// Two players attack the first enemy standing while two randomly
+    /// acting enemies hit back, with misses, crits and variance in play.
+    fn fight(seed: u64) -> Vec<ActionOutcome> {
+        let mut damage = damage_table();
+        damage.formula.variance = 0.2;
+        damage.formula.crit_chance = 0.2;
+        damage.formula.hit_chance = 0.8;
+        let (items, skills, statuses) = Default::default();
+        let rules = Rules {
+            damage: &damage,
+            items: &items,
+            skills: &skills,
+            statuses: &statuses,
+        };
+        let mut rng = BattleRng::from_seed(seed);
+        let units = vec![
+            unit(Team::Player, json!({ "max_hp": 60, "hp": 60 })),
+            unit(Team::Player, json!({ "max_hp": 40, "hp": 40, "dmg": 14 })),
+            unit(Team::Enemy, json!({ "max_hp": 50, "hp": 50 })),
+            unit(Team::Enemy, json!({ "max_hp": 70, "hp": 70, "dmg": 8 })),
+        ];
+        let mut battle = Battle::new(units, rules, &mut rng);
+        let mut outcomes = Vec::new();
+        for _round in 0..100 {
+            for unit in 0..battle.units.len() {
+                if !battle.units[unit].is_alive() || battle.start_turn(unit).skip {
+                    continue;
+                }
+                let action = match battle.units[unit].team {
+                    Team::Player => {
+                        battle
+                            .living(Team::Enemy)
+                            .next()
+                            .map(|receive| Action::Attack {
+                                send: unit,
+                                receive,
+                            })
+                    }
+                    Team::Enemy => battle.enemy_action(unit),
+                };
+                let action = match action {
+                    Some(action) => action,
+                    None => return outcomes,
+                };
+                outcomes.extend(battle.resolve_action(action));
+                battle.end_turn(unit);
+            }
+        }
+        outcomes
+    }
+
+    fn skill_table() -> SkillTable {
+        serde_json::from_value(json!({
+            "skills": [
+                { "id": "mend", "name": "Mend", "mp_cost": 4, "target": "ally", "heal": 30 },
+                { "id": "flare", "name": "Flare", "mp_cost": 2, "target": "enemy" }
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn decide(ai: &mut EnemyAi, units: &[Combatant], seed: u64) -> Option<Action> {
+        ai.decide(0, units, &skill_table(), &mut BattleRng::from_seed(seed))
+    }
+
+    #[test]
+    fn aggressive_enemy_attacks_the_foe_with_least_hp() {
+        let units = [
+            unit(Team::Enemy, json!({})),
+            unit(Team::Player, json!({ "hp": 80 })),
+            unit(Team::Player, json!({ "hp": 30 })),
+            unit(Team::Player, json!({ "hp": 0 })),
+            unit(Team::Player, json!({ "hp": 60 })),
+        ];
+        let mut ai = EnemyAi::new(AiProfile::Aggressive);
+        for seed in 0..8 {
+            assert_eq!(
+                decide(&mut ai, &units, seed),
+                Some(Action::Attack {
+                    send: 0,
+                    receive: 2
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn exploiting_enemy_attacks_a_foe_weak_to_it() {
+        let units = [
+            unit(Team::Enemy, json!({})),
+            unit(Team::Player, json!({ "resistances": ["S"] })),
+            unit(Team::Player, json!({ "weaknesses": ["S"] })),
+            unit(Team::Player, json!({ "weaknesses": ["F"] })),
+        ];
+        let mut ai = EnemyAi::new(AiProfile::ExploitWeakness);
+        for seed in 0..8 {
+            assert_eq!(
+                decide(&mut ai, &units, seed),
+                Some(Action::Attack {
+                    send: 0,
+                    receive: 2
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn defensive_enemy_heals_hurt_allies_and_guards_when_it_cannot() {
+        let mut ai = EnemyAi::new(AiProfile::Defensive { threshold: 0.5 });
+        let healer = json!({ "max_mp": 10, "mp": 10, "skills": ["mend"] });
+        let mut units = vec![
+            unit(Team::Enemy, healer),
+            unit(Team::Enemy, json!({ "hp": 30 })),
+            unit(Team::Player, json!({})),
+        ];
+        assert_eq!(
+            decide(&mut ai, &units, 0),
+            Some(Action::UseSkill {
+                user: 0,
+                target: 1,
+                skill: 0
+            })
+        );
+
+        units[0].receive.hp = 20;
+        units[0].receive.mp = 0;
+        assert_eq!(decide(&mut ai, &units, 0), Some(Action::Defend { unit: 0 }));
+
+        units[0].receive.hp = 100;
+        units[1].receive.hp = 100;
+        assert_eq!(
+            decide(&mut ai, &units, 0),
+            Some(Action::Attack {
+                send: 0,
+                receive: 2
+            })
+        );
+    }
+
+    #[test]
+    fn scripted_enemy_cycles_through_its_sequence() {
+        let sequence = vec![
+            AiStep::Defend,
+            AiStep::Skill {
+                id: "flare".to_string(),
+            },
+            AiStep::Attack,
+        ];
+        let mut ai = EnemyAi::new(AiProfile::Scripted { sequence });
+        let units = [
+            unit(
+                Team::Enemy,
+                json!({ "max_mp": 10, "mp": 10, "skills": ["flare"] }),
+            ),
+            unit(Team::Player, json!({})),
+        ];
+        let expected = [
+            Action::Defend { unit: 0 },
+            Action::UseSkill {
+                user: 0,
+                target: 1,
+                skill: 1,
+            },
+            Action::Attack {
+                send: 0,
+                receive: 1,
+            },
+        ];
+        for round in 0..2 {
+            for (i, action) in expected.iter().enumerate() {
+                let seed = (round * expected.len() + i) as u64;
+                assert_eq!(decide(&mut ai, &units, seed), Some(*action));
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_replays_the_same_fight() {
+        let first = fight(7);
+        let attacks: Vec<&AttackOutcome> = first.iter().filter_map(|o| o.attack.as_ref()).collect();
+        assert!(attacks.iter().any(|a| a.missed));
+        assert!(attacks.iter().any(|a| a.crit));
+        assert_eq!(first, fight(7));
+        assert_ne!(first, fight(8));
+    }
+
+    #[test]
+    fn defense_mitigates_damage() {
+        let formula = DamageFormula::default();
+        assert_eq!(formula.mitigation(0), 1.0);
+        assert_eq!(formula.mitigation(20), 0.5);
+
+        let mut attacker = unit(Team::Player, json!({}));
+        let mut target = unit(Team::Enemy, json!({ "defense": 20 }));
+        let outcome = attack(&mut attacker, &mut target);
+        assert_eq!((outcome.raw_dmg, outcome.final_dmg), (10, 5));
+        assert_eq!(target.receive.hp, 95);
+    }
+
+    #[test]
+    fn hit_chance_is_clamped() {
+        let formula = DamageFormula::default();
+        let stats = |accuracy, evasion| StatBlock {
+            accuracy,
+            evasion,
+            ..StatBlock::default()
+        };
+        assert_eq!(formula.hit_chance(&stats(0, 0), &stats(0, 0)), 1.0);
+        assert!((formula.hit_chance(&stats(0, 0), &stats(0, 30)) - 0.7).abs() < 1e-6);
+        assert_eq!(formula.hit_chance(&stats(0, 0), &stats(0, 500)), 0.2);
+        assert_eq!(formula.hit_chance(&stats(500, 0), &stats(0, 0)), 1.0);
+
+        let unreachable_floor = DamageFormula {
+            min_hit_chance: 2.0,
+            ..DamageFormula::default()
+        };
+        assert_eq!(unreachable_floor.hit_chance(&stats(0, 0), &stats(0, 500)), 1.0);
+    }
+
+    #[test]
+    fn luck_raises_crit_chance_and_crits_multiply_damage() {
+        let formula = DamageFormula {
+            crit_chance: 0.05,
+            ..DamageFormula::default()
+        };
+        let lucky = StatBlock {
+            luck: 10,
+            ..StatBlock::default()
+        };
+        assert!((formula.crit_chance(&lucky) - 0.15).abs() < 1e-6);
+        assert_eq!(formula.crit_chance(&StatBlock::default()), 0.05);
+
+        let mut attacker = unit(Team::Player, json!({}));
+        let mut target = unit(Team::Enemy, json!({}));
+        let crit = HitRoll { crit: true, ..HIT };
+        let outcome = resolve_attack(
+            &mut attacker.send,
+            &attacker.stats.current,
+            &mut attacker.receive,
+            &mut target.receive,
+            &target.stats.current,
+            crit,
+            &damage_table(),
+        );
+        assert!(outcome.crit);
+        assert_eq!(outcome.final_dmg, 15);
+    }
+}
@@ -4,14 +4,33 @@ pub struct CameraPlugin;
 #[derive(Component)]
 pub struct MainCamera;
 
+/// The main camera centres on this entity, or on the origin when none exists.
+#[derive(Component)]
+pub struct CameraTarget;
+
 fn setup(mut commands: Commands) {
     commands
         .spawn_bundle(Camera2dBundle::default())
         .insert(MainCamera);
 }
 
+fn follow_target(
+    mut camera_q: Query<&mut Transform, (With<MainCamera>, Without<CameraTarget>)>,
+    target_q: Query<&Transform, (With<CameraTarget>, Without<MainCamera>)>,
+) {
+    let focus = target_q
+        .iter()
+        .next()
+        .map_or(Vec2::ZERO, |t| t.translation.truncate());
+    for mut camera in camera_q.iter_mut() {
+        camera.translation.x = focus.x;
+        camera.translation.y = focus.y;
+    }
+}
+
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system_to_stage(StartupStage::PreStartup, setup);
+        app.add_startup_system_to_stage(StartupStage::PreStartup, setup)
+            .add_system_to_stage(CoreStage::PostUpdate, follow_target);
     }
 }
@@ -1,88 +1,472 @@
 // pub mod gui;
 
-use bevy::{prelude::*, render::camera::RenderTarget};
-use rand::seq::IteratorRandom;
+use std::collections::VecDeque;
+
+use bevy::{
+    asset::LoadState, ecs::system::SystemParam, prelude::*, render::camera::RenderTarget,
+    sprite::Anchor,
+};
+use serde::Deserialize;
 
 use crate::{
+    battle::{
+        defend, start_turn, Action, Affinity, AttackOutcome, AttackReceive, AttackSend, Battle,
+        BattleRng, Combatant, DamageTable, EnemyAi, Initiative, ItemDef, ItemEffect, ItemOutcome,
+        ItemTable, Rules, SkillTable, Stats, StatusEffects, StatusTable, TargetShape, Team,
+        TurnStart, UnknownDamageType,
+    },
     camera::MainCamera,
+    controls::{Controls, FieldInput, InputAction},
+    encounter::{Background, CurrentEncounter, EncounterAsset, EncounterRequest, EncounterSlot},
     enemy::Enemy,
+    gui::{CombatActions, CombatButtonEvent, DefeatChoice, DefeatEvent},
     player::Player,
-    save_load::{load_units, UnitJson},
+    progression::{gain_xp, GrowthTable, LevelCurve},
+    save_load::{
+        load_party, store_party, Inventory, LoadError, LoadErrors, Party, PartyMember, SaveSlots,
+        UnitJson,
+    },
     states::{CombatPhases, Views},
 };
 
 pub struct CombatPlugin;
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum DamageType {
-    Piercing,
-    Bludgeoning,
-    Slashing,
+/// Random source for the running fight. Set `SEED_VAR` to a number, or insert
+/// `CombatRng(BattleRng::from_seed(..))` before adding `CombatPlugin`, to
+/// replay a battle deterministically.
+pub struct CombatRng(pub BattleRng);
+
+/// Environment variable holding the seed of `CombatRng`.
+pub const SEED_VAR: &str = "JRPG_SEED";
+
+impl Default for CombatRng {
+    fn default() -> Self {
+        let seed = std::env::var(SEED_VAR)
+            .ok()
+            .and_then(|seed| seed.parse().ok());
+        match seed {
+            Some(seed) => CombatRng(BattleRng::from_seed(seed)),
+            None => CombatRng(BattleRng::from_entropy()),
+        }
+    }
+}
+
+/// How turns are handed out. Set per fight from `EncounterAsset::turn_mode`
+/// once the encounter is loaded. `Initiative` interleaves both teams by speed
+/// instead of alternating whole team phases.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TurnMode {
+    #[default]
+    TeamPhase,
+    Initiative,
+}
+
+/// Turn order of the running fight in `TurnMode::Initiative`.
+#[derive(Default)]
+pub struct TurnQueue(pub Initiative<Entity>);
+
+/// Team-phase mode: whether the unit that acts next is picked at random
+/// instead of by the player.
+#[derive(Default)]
+pub struct AutoSelect(pub bool);
+
+/// Menus the player went through to reach the current one, oldest first.
+/// Cancelling returns to the last one without using the turn.
+#[derive(Default)]
+pub struct MenuStack(pub Vec<CombatPhases>);
+
+impl MenuStack {
+    /// Switches to `next`, remembering the current phase to come back to.
+    pub fn open(&mut self, phase: &mut State<CombatPhases>, next: CombatPhases) {
+        let current = *phase.current();
+        if phase.overwrite_set(next).is_ok() {
+            self.0.push(current);
+        }
+    }
+
+    /// Returns to the phase the current menu was opened from.
+    pub fn back(&mut self, phase: &mut State<CombatPhases>) {
+        if let Some(previous) = self.0.pop() {
+            if phase.overwrite_set(previous).is_err() {
+                self.0.push(previous);
+            }
+        }
+    }
+}
+
+/// Unit the highlight sits on while the player picks who acts or what to
+/// target.
+#[derive(Default)]
+struct SelectionCursor(Option<Entity>);
+
+/// Enemy under the mouse, or else the enemy the target cursor rests on. The
+/// HUD shows its details.
+#[derive(Default)]
+pub struct Inspected(pub Option<Entity>);
+
+/// What the last won battle paid out, for the victory screen. Items are
+/// listed by name with the count found, level ups by member name with the
+/// level reached.
+#[derive(Default)]
+pub struct BattleRewards {
+    pub xp: u32,
+    pub gold: u32,
+    pub items: Vec<(String, u32)>,
+    pub level_ups: Vec<(String, u32)>,
 }
 
+/// Seconds each enemy action stays on screen before the next enemy acts.
+/// Insert before adding `CombatPlugin` to change it.
+pub struct EnemyTurnDelay(pub f32);
+
+impl Default for EnemyTurnDelay {
+    fn default() -> Self {
+        EnemyTurnDelay(0.8)
+    }
+}
+
+/// Enemies still to act in the running enemy phase, and the wait before
+/// the next one does.
+#[derive(Default)]
+struct EnemyQueue {
+    pending: VecDeque<Entity>,
+    /// The enemy whose action is on screen. Its turn ends when the next
+    /// enemy acts.
+    acting: Option<Entity>,
+    timer: Timer,
+}
+
+/// How far a unit lunges towards its target when it acts.
+const ATTACK_LUNGE: f32 = 40.0;
+
+/// Plays a lunge from `origin`, `direction` is -1.0 to the left or 1.0 to
+/// the right.
 #[derive(Component)]
-pub struct AttackReceive {
-    pub hp: u32,
-    pub max_hp: u32,
-    pub weaknesses: Vec<DamageType>,
-    pub resistances: Vec<DamageType>,
+struct AttackAnimation {
+    timer: Timer,
+    origin: Vec3,
+    direction: f32,
 }
 
+impl AttackAnimation {
+    fn new(origin: Vec3, direction: f32) -> Self {
+        AttackAnimation {
+            timer: Timer::from_seconds(0.3, false),
+            origin,
+            direction,
+        }
+    }
+}
+
+/// How long a hit unit flashes red and shakes.
+const HIT_FLASH_SECS: f32 = 0.25;
+const HIT_FLASH_COLOR: Color = Color::rgb(1.0, 0.3, 0.3);
+const HIT_SHAKE: f32 = 6.0;
+
+/// Flashes and shakes a unit that just took damage around `origin`.
 #[derive(Component)]
-pub struct AttackSend {
-    pub used: bool,
-    pub dmg: u32,
-    pub dmg_type: DamageType,
+struct HitFlash {
+    timer: Timer,
+    origin: Vec3,
 }
 
+impl HitFlash {
+    fn new(origin: Vec3) -> Self {
+        HitFlash {
+            timer: Timer::from_seconds(HIT_FLASH_SECS, false),
+            origin,
+        }
+    }
+}
+
+/// A defeated enemy fading out. It has lost its combat components, so it
+/// no longer acts, counts or gets targeted, and is despawned once faded.
+#[derive(Component)]
+struct Dying {
+    timer: Timer,
+}
+
+impl Dying {
+    fn new() -> Self {
+        Dying {
+            timer: Timer::from_seconds(0.6, false),
+        }
+    }
+}
+
+/// A number rising and fading above a unit that was hit or healed.
+#[derive(Component)]
+struct DamageNumber {
+    timer: Timer,
+}
+
+const DAMAGE_NUMBER_RISE: f32 = 50.0;
+const DAMAGE_COLOR: Color = Color::WHITE;
+const WEAK_COLOR: Color = Color::rgb(1.0, 0.6, 0.1);
+const RESIST_COLOR: Color = Color::rgb(0.6, 0.7, 0.9);
+const HEAL_COLOR: Color = Color::rgb(0.3, 1.0, 0.4);
+const MISS_COLOR: Color = Color::GRAY;
+const STATUS_DAMAGE_COLOR: Color = Color::rgb(0.7, 0.4, 0.9);
+
 #[derive(Component)]
 struct Highlight;
 
+/// The unit whose turn it is.
+#[derive(Component)]
+pub struct Active;
+
+#[derive(Component)]
+struct GuardIndicator;
+
+/// Text over a unit listing the icons of its statuses.
+#[derive(Component)]
+struct StatusLabel;
+
+/// Filled part of the hp bar over an enemy, sized to its remaining hp.
+#[derive(Component)]
+struct HpBarFill;
+
+const HP_BAR_SIZE: Vec2 = Vec2::new(48.0, 6.0);
+const HP_BAR_COLOR: Color = Color::rgb(0.85, 0.2, 0.2);
+
+/// Party members at 0 hp stay on the field, greyed out, so they can be
+/// revived. Enemies are despawned instead.
 #[derive(Component)]
-struct Active;
+pub struct KnockedOut;
+
+const KNOCKED_OUT_COLOR: Color = Color::rgba(0.5, 0.5, 0.5, 0.5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActionKind {
+    Attack,
+    /// Use the item with this `ItemDef::id` from the `Inventory`.
+    Item(String),
+    /// Use the skill with this `SkillDef::id`.
+    Skill(String),
+}
+
+/// What the active unit is picking a target for.
+pub struct PendingAction(pub ActionKind);
+
+impl Default for PendingAction {
+    fn default() -> Self {
+        PendingAction(ActionKind::Attack)
+    }
+}
 
 pub struct CombatEvent {
     pub send: Entity,
     pub receive: Entity,
+    pub kind: ActionKind,
 }
 
-fn parse_dmg_type(character: &char) -> DamageType {
-    match character {
-        'P' => DamageType::Piercing,
-        'B' => DamageType::Bludgeoning,
-        'S' => DamageType::Slashing,
-        _ => DamageType::Slashing,
+/// What one resolved action did, sent by `read_events` once per attack, item
+/// use and skill hit so the UI, the log and audio never re-derive it.
+#[derive(Debug, Clone)]
+pub struct CombatResultEvent {
+    pub attacker: Entity,
+    pub target: Entity,
+    pub kind: ActionKind,
+    pub affinity: Affinity,
+    pub raw_dmg: u32,
+    pub multiplier: f32,
+    pub final_dmg: u32,
+    pub healed: u32,
+    /// Hp left on whoever took the hit: the attacker when `reflected`.
+    pub hp_left: u32,
+    pub killed: bool,
+    pub revived: bool,
+    pub reflected: bool,
+    pub missed: bool,
+    pub crit: bool,
+    /// The action never went off, e.g. a skill its user cannot pay for.
+    pub failed: bool,
+}
+
+/// Damage over time a unit took as its turn started, sent so the log and
+/// the floating numbers show it like any other hit.
+#[derive(Debug, Clone)]
+pub struct StatusDamageEvent {
+    pub unit: Entity,
+    pub damage: u32,
+    pub killed: bool,
+}
+
+impl StatusDamageEvent {
+    fn new(unit: Entity, start: &TurnStart, hp_left: u32) -> Option<Self> {
+        (start.damage > 0).then_some(StatusDamageEvent {
+            unit,
+            damage: start.damage,
+            killed: hp_left == 0,
+        })
     }
 }
+
+impl CombatResultEvent {
+    fn new(event: &CombatEvent) -> Self {
+        CombatResultEvent {
+            attacker: event.send,
+            target: event.receive,
+            kind: event.kind.clone(),
+            affinity: Affinity::Normal,
+            raw_dmg: 0,
+            multiplier: 1.0,
+            final_dmg: 0,
+            healed: 0,
+            hp_left: 0,
+            killed: false,
+            revived: false,
+            reflected: false,
+            missed: false,
+            crit: false,
+            failed: false,
+        }
+    }
+
+    fn aimed_at(self, target: Entity) -> Self {
+        CombatResultEvent { target, ..self }
+    }
+
+    fn with_attack(self, outcome: &AttackOutcome) -> Self {
+        CombatResultEvent {
+            affinity: outcome.affinity,
+            raw_dmg: outcome.raw_dmg,
+            multiplier: outcome.multiplier,
+            final_dmg: outcome.final_dmg,
+            hp_left: outcome.hp_left,
+            killed: outcome.killed,
+            reflected: outcome.hit_sender(),
+            missed: outcome.missed,
+            crit: outcome.crit,
+            ..self
+        }
+    }
+
+    fn with_item(self, item: &ItemDef, outcome: &ItemOutcome) -> Self {
+        let (raw_dmg, final_dmg, healed) = match item.effect {
+            ItemEffect::Damage { amount, .. } => (amount, outcome.amount, 0),
+            ItemEffect::Heal { .. } | ItemEffect::Revive { .. } => (0, 0, outcome.amount),
+            ItemEffect::Cure { .. } => (0, 0, 0),
+        };
+        CombatResultEvent {
+            affinity: outcome.affinity,
+            raw_dmg,
+            multiplier: outcome.multiplier,
+            final_dmg,
+            healed,
+            hp_left: outcome.hp_left,
+            killed: outcome.killed,
+            revived: outcome.revived,
+            ..self
+        }
+    }
+
+    fn with_heal(self, healed: u32, hp_left: u32) -> Self {
+        CombatResultEvent {
+            healed,
+            hp_left,
+            ..self
+        }
+    }
+
+    fn missed(self) -> Self {
+        CombatResultEvent {
+            missed: true,
+            ..self
+        }
+    }
+
+    fn failed(self) -> Self {
+        CombatResultEvent {
+            failed: true,
+            ..self
+        }
+    }
+}
+
+/// Units are validated when loaded, so this only happens when the damage
+/// table no longer matches what was validated.
+fn unit_error(path: &str, unit: &UnitJson, error: UnknownDamageType) -> LoadError {
+    LoadError::Malformed {
+        path: path.to_string(),
+        reason: format!("unit '{}': {}", unit.name, error),
+    }
+}
+
+/// `path` names the file the units came from in errors.
 fn spawn_team<T: Component + Copy>(
     commands: &mut Commands,
     asset_server: &Res<AssetServer>,
-    units: Vec<UnitJson>,
+    damage_table: &DamageTable,
+    path: &str,
+    units: Vec<(UnitJson, Vec2)>,
     team: T,
-    x_offset: f32,
-) {
-    let mut i = -100.0;
-    for unit in units.iter() {
-        commands
-            .spawn_bundle(SpriteBundle {
-                texture: asset_server.load(format!("sprites/{}.png", unit.sprite).as_str()),
-                transform: Transform::from_translation(Vec3::new(x_offset, i, 1.0)),
+) -> Result<Vec<Entity>, LoadError> {
+    let mut entities = Vec::new();
+    for (unit, position) in units.iter() {
+        let send = AttackSend::from_json(unit, damage_table)
+            .map_err(|e| unit_error(path, unit, e))?;
+        let receive = AttackReceive::from_json(unit, damage_table)
+            .map_err(|e| unit_error(path, unit, e))?;
+        let knocked_out = receive.hp == 0;
+        let mut entity = commands.spawn_bundle(SpriteBundle {
+            texture: asset_server.load(format!("sprites/{}.png", unit.sprite).as_str()),
+            sprite: Sprite {
+                color: if knocked_out {
+                    KNOCKED_OUT_COLOR
+                } else {
+                    Color::WHITE
+                },
                 ..default()
-            })
+            },
+            transform: Transform::from_translation(position.extend(1.0)),
+            ..default()
+        });
+        if knocked_out {
+            entity.insert(KnockedOut);
+        }
+        let entity = entity
+            .insert(Name::new(unit.name.clone()))
             .insert(team)
-            .insert(AttackSend {
-                used: false,
-                dmg: unit.dmg,
-                dmg_type: parse_dmg_type(&unit.dmg_type),
+            .insert(send)
+            .insert(receive)
+            .insert(Stats::from_json(unit))
+            .insert(StatusEffects::default())
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(Text2dBundle {
+                        text: Text::from_section(
+                            "",
+                            TextStyle {
+                                font: asset_server.load("fonts/SourceCodePro.ttf"),
+                                font_size: 14.0,
+                                color: Color::WHITE,
+                            },
+                        )
+                        .with_alignment(TextAlignment::CENTER),
+                        transform: Transform::from_xyz(0.0, 40.0, 1.0),
+                        ..default()
+                    })
+                    .insert(StatusLabel);
+                parent
+                    .spawn_bundle(SpriteBundle {
+                        texture: asset_server.load("sprites/highlight.png"),
+                        sprite: Sprite {
+                            color: Color::rgba(0.3, 0.5, 1.0, 0.8),
+                            ..default()
+                        },
+                        transform: Transform::from_xyz(0.0, 0.0, -0.5)
+                            .with_scale(Vec3::splat(1.2)),
+                        visibility: Visibility { is_visible: false },
+                        ..default()
+                    })
+                    .insert(GuardIndicator);
             })
-            .insert(AttackReceive {
-                hp: unit.hp,
-                max_hp: unit.max_hp,
-                weaknesses: unit.weaknesses.iter().map(parse_dmg_type).collect(),
-                resistances: unit.resistances.iter().map(parse_dmg_type).collect(),
-            });
-        i += 100.0;
+            .id();
+        entities.push(entity);
     }
+    Ok(entities)
 }
 
 fn spawn_highlight(mut commands: Commands, asset_server: Res<AssetServer>) {
@@ -95,152 +479,898 @@ fn spawn_highlight(mut commands: Commands, asset_server: Res<AssetServer>) {
         .insert(Highlight);
 }
 
-fn toggle_highlight(mut highlight_q: Query<&mut Visibility, With<Highlight>>) {
-    let mut highlight = highlight_q.single_mut();
-    highlight.is_visible = !highlight.is_visible;
-}
-
 fn move_highlight_to_active(
     mut highlight_q: Query<&mut Transform, (With<Highlight>, Without<Active>)>,
     active_q: Query<&Transform, (With<Active>, Without<Highlight>)>,
+    units: Query<&Transform, Without<Highlight>>,
+    cursor: Res<SelectionCursor>,
     mut phase: ResMut<State<CombatPhases>>,
 ) {
-    if active_q.is_empty() && phase.overwrite_set(CombatPhases::SelectAction).is_ok() {}
     let mut highlight = highlight_q.single_mut();
     if let Some(active) = active_q.iter().next() {
         highlight.translation = active.translation;
-        if phase.overwrite_set(CombatPhases::SelectAction).is_ok() {}
+        let _ = phase.overwrite_set(CombatPhases::SelectAction);
+        return;
+    }
+    match cursor.0.and_then(|entity| units.get(entity).ok()) {
+        Some(candidate) => highlight.translation = candidate.translation,
+        None => {
+            let _ = phase.overwrite_set(CombatPhases::SelectAction);
+        }
     }
 }
 
-fn set_random_active_unit(
+/// Team-phase mode: the player picks which unit acts by clicking its sprite,
+/// or by cycling with up/down and confirming. The highlight follows the unit
+/// under the selection cursor. With `AutoSelect` on, a random ready unit is
+/// picked instead.
+///
+/// Starting a unit's turn drops the guard it raised on its last turn and
+/// runs its statuses. Units that lose the turn, or are knocked out by damage
+/// over time, count as having acted and another unit has to be picked.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn select_active_unit(
     mut commands: Commands,
-    player_units: Query<(Entity, &AttackSend), With<Player>>,
+    mode: Res<TurnMode>,
+    auto: Res<AutoSelect>,
+    mut controls: Controls,
+    mouse: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut cursor: ResMut<SelectionCursor>,
+    mut player_units: Query<
+        (Entity, &Transform, &mut AttackSend, &mut AttackReceive, &mut StatusEffects),
+        (With<Player>, Without<KnockedOut>),
+    >,
+    active: Query<(), With<Active>>,
+    players: Query<(), With<Player>>,
+    mut sprites: Query<&mut Sprite>,
+    mut rng: ResMut<CombatRng>,
+    mut status_damage: EventWriter<StatusDamageEvent>,
 ) {
-    let mut rng = rand::thread_rng();
-    let player = player_units
+    if *mode != TurnMode::TeamPhase || !active.is_empty() {
+        cursor.0 = None;
+        return;
+    }
+    // Top to bottom, the order units stand on screen.
+    let mut ready: Vec<(Entity, Vec3)> = player_units
         .iter()
-        .filter(|(_e, s)| !s.used)
-        .choose(&mut rng);
-    if let Some((e, _s)) = player {
-        commands.entity(e).insert(Active);
+        .filter(|(_e, _t, s, _r, _st)| !s.used)
+        .map(|(e, t, _s, _r, _st)| (e, t.translation))
+        .collect();
+    ready.sort_by(|a, b| b.1.y.total_cmp(&a.1.y));
+    if ready.is_empty() {
+        cursor.0 = None;
+        return;
+    }
+
+    let chosen = if auto.0 {
+        cursor.0 = None;
+        rng.0.choose(ready.iter().map(|(e, _t)| *e))
+    } else {
+        let len = ready.len();
+        let mut index = cursor
+            .0
+            .and_then(|c| ready.iter().position(|(e, _t)| *e == c))
+            .unwrap_or(0);
+        if controls.take(InputAction::Up) {
+            index = (index + len - 1) % len;
+        }
+        if controls.take(InputAction::Down) {
+            index = (index + 1) % len;
+        }
+        cursor.0 = Some(ready[index].0);
+
+        let mut chosen = None;
+        if controls.take(InputAction::Confirm) {
+            chosen = cursor.0;
+        }
+        if mouse.just_pressed(MouseButton::Left) {
+            let (camera, camera_transform) = q_camera.single();
+            if let Some(world_pos) = cursor_world_position(&windows, camera, camera_transform) {
+                if let Some((e, _t)) = ready.iter().find(|(_e, t)| t.distance(world_pos) <= 32.0) {
+                    cursor.0 = Some(*e);
+                    chosen = Some(*e);
+                }
+            }
+        }
+        chosen
+    };
+
+    let entity = match chosen {
+        Some(entity) => entity,
+        None => return,
+    };
+    let (_e, _t, mut send, mut receive, mut statuses) = match player_units.get_mut(entity) {
+        Ok(unit) => unit,
+        Err(_) => return,
+    };
+    if start_player_turn(
+        entity,
+        &mut send,
+        &mut receive,
+        &mut statuses,
+        &mut status_damage,
+    ) {
+        commands.entity(entity).insert(Active);
+        cursor.0 = None;
+    } else if receive.hp == 0 {
+        knock_out(&mut commands, &players, &mut sprites, entity);
     }
 }
 
-fn check_all_acted(
-    player_units: Query<&AttackSend, With<Player>>,
+/// Cancel, or a right click, backs out of the open menu or of target
+/// selection.
+fn cancel_menu(
+    mut controls: Controls,
+    mouse: Res<Input<MouseButton>>,
+    mut menus: ResMut<MenuStack>,
+    mut phase: ResMut<State<CombatPhases>>,
+) {
+    if controls.take(InputAction::Cancel) || mouse.just_pressed(MouseButton::Right) {
+        menus.back(&mut phase);
+    }
+}
+
+/// Back at the action bar nothing is left to back out of.
+fn clear_menu_stack(mut menus: ResMut<MenuStack>) {
+    menus.0.clear();
+}
+
+fn toggle_auto_select(mut controls: Controls, mut auto: ResMut<AutoSelect>) {
+    if controls.take(InputAction::ToggleAuto) {
+        auto.0 = !auto.0;
+    }
+}
+
+/// Starts a player unit's turn, see `battle::start_turn`. Returns false when
+/// the turn is lost to a status or to being knocked out by damage over time.
+fn start_player_turn(
+    entity: Entity,
+    send: &mut AttackSend,
+    receive: &mut AttackReceive,
+    statuses: &mut StatusEffects,
+    status_damage: &mut EventWriter<StatusDamageEvent>,
+) -> bool {
+    let start = start_turn(send, receive, statuses);
+    if let Some(event) = StatusDamageEvent::new(entity, &start, receive.hp) {
+        status_damage.send(event);
+    }
+    !start.skip && receive.hp > 0
+}
+
+/// Initiative mode: hands the turn to whoever is next in the `TurnQueue`.
+/// Player units start their turn here, enemies play theirs out in the
+/// `Enemy` phase.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn next_in_queue(
+    mut commands: Commands,
+    mode: Res<TurnMode>,
+    mut queue: ResMut<TurnQueue>,
+    mut units: ParamSet<(
+        Query<(Entity, &Stats, &AttackReceive), Without<KnockedOut>>,
+        Query<
+            (&mut AttackSend, &mut AttackReceive, &mut StatusEffects),
+            (With<Player>, Without<KnockedOut>),
+        >,
+    )>,
+    players: Query<(), With<Player>>,
+    mut sprites: Query<&mut Sprite>,
+    mut status_damage: EventWriter<StatusDamageEvent>,
     mut phase: ResMut<State<CombatPhases>>,
 ) {
-    if player_units
+    if *mode != TurnMode::Initiative {
+        return;
+    }
+    loop {
+        queue.0.sync(
+            units
+                .p0()
+                .iter()
+                .filter(|(_e, _s, r)| r.hp > 0)
+                .map(|(e, s, _r)| (e, s.current.speed)),
+        );
+        let entity = match queue.0.next() {
+            Some(entity) => entity,
+            None => return,
+        };
+        if players.get(entity).is_err() {
+            commands.entity(entity).insert(Active);
+            let _ = phase.overwrite_set(CombatPhases::Enemy);
+            return;
+        }
+        let mut player_units = units.p1();
+        let (mut send, mut receive, mut statuses) = match player_units.get_mut(entity) {
+            Ok(unit) => unit,
+            Err(_) => continue,
+        };
+        if start_player_turn(
+            entity,
+            &mut send,
+            &mut receive,
+            &mut statuses,
+            &mut status_damage,
+        ) {
+            commands.entity(entity).insert(Active);
+            return;
+        }
+        if receive.hp == 0 {
+            knock_out(&mut commands, &players, &mut sprites, entity);
+        }
+    }
+}
+
+fn sync_turn_queue(
+    mode: Res<TurnMode>,
+    mut queue: ResMut<TurnQueue>,
+    units: Query<(Entity, &Stats, &AttackReceive), Without<KnockedOut>>,
+) {
+    if *mode == TurnMode::Initiative {
+        queue.0.sync(
+            units
+                .iter()
+                .filter(|(_e, _s, r)| r.hp > 0)
+                .map(|(e, s, _r)| (e, s.current.speed)),
+        );
+    }
+}
+
+fn defend_active(
+    mut button_events: EventReader<CombatButtonEvent>,
+    mut active: Query<(&mut AttackSend, &mut AttackReceive), With<Active>>,
+    mut phase: ResMut<State<CombatPhases>>,
+) {
+    if !button_events
         .iter()
-        .filter(|s| !s.used)
-        .peekable()
-        .peek()
-        .is_none()
-        && phase.overwrite_set(CombatPhases::Enemy).is_ok()
-    {}
+        .any(|e| matches!(e.action, CombatActions::Defend))
+    {
+        return;
+    }
+    if let Some((mut send, mut receive)) = active.iter_mut().next() {
+        defend(&mut send, &mut receive);
+        let _ = phase.overwrite_set(CombatPhases::SelectActive);
+    }
+}
+
+/// Fleeing always succeeds, but only where the encounter allows it.
+fn flee_active(
+    mut button_events: EventReader<CombatButtonEvent>,
+    current: Res<CurrentEncounter>,
+    encounters: Res<Assets<EncounterAsset>>,
+    mut phase: ResMut<State<CombatPhases>>,
+) {
+    if !button_events
+        .iter()
+        .any(|e| matches!(e.action, CombatActions::Flee))
+    {
+        return;
+    }
+    let allowed = encounters
+        .get(&current.handle)
+        .is_some_and(|e| e.flee_allowed);
+    if allowed {
+        let _ = phase.overwrite_set(CombatPhases::Fled);
+    }
 }
+
+fn sync_status_modifiers(
+    mut units: Query<
+        (
+            &StatusEffects,
+            &mut Stats,
+            &mut AttackSend,
+            &mut AttackReceive,
+        ),
+        Changed<StatusEffects>,
+    >,
+) {
+    for (statuses, mut stats, mut send, mut receive) in units.iter_mut() {
+        statuses.sync(&mut send, &mut receive);
+        stats.recalculate(statuses);
+    }
+}
+
+fn clear_knocked_out_statuses(mut units: Query<&mut StatusEffects, Added<KnockedOut>>) {
+    for mut statuses in units.iter_mut() {
+        statuses.clear();
+    }
+}
+
+fn show_status_icons(
+    asset_server: Res<AssetServer>,
+    units: Query<(&StatusEffects, &Children), Changed<StatusEffects>>,
+    mut labels: Query<&mut Text, With<StatusLabel>>,
+) {
+    for (statuses, children) in units.iter() {
+        for &child in children.iter() {
+            if let Ok(mut text) = labels.get_mut(child) {
+                text.sections = statuses
+                    .active
+                    .iter()
+                    .map(|status| {
+                        let [r, g, b] = status.def.color;
+                        TextSection::new(
+                            format!("{} ", status.def.icon),
+                            TextStyle {
+                                font: asset_server.load("fonts/SourceCodePro.ttf"),
+                                font_size: 14.0,
+                                color: Color::rgb(r, g, b),
+                            },
+                        )
+                    })
+                    .collect();
+            }
+        }
+    }
+}
+
+fn hp_bar_size(receive: &AttackReceive) -> Vec2 {
+    let fraction = receive.hp as f32 / receive.max_hp.max(1) as f32;
+    Vec2::new(HP_BAR_SIZE.x * fraction, HP_BAR_SIZE.y)
+}
+
+/// Spawned full, `show_hp_bars` sizes it once the unit's `AttackReceive` is
+/// in place.
+fn spawn_hp_bar(parent: &mut ChildBuilder) {
+    let position = Vec3::new(-HP_BAR_SIZE.x / 2.0, 56.0, 1.0);
+    parent.spawn_bundle(SpriteBundle {
+        sprite: Sprite {
+            color: Color::rgba(0.0, 0.0, 0.0, 0.7),
+            custom_size: Some(HP_BAR_SIZE),
+            anchor: Anchor::CenterLeft,
+            ..default()
+        },
+        transform: Transform::from_translation(position),
+        ..default()
+    });
+    parent
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: HP_BAR_COLOR,
+                custom_size: Some(HP_BAR_SIZE),
+                anchor: Anchor::CenterLeft,
+                ..default()
+            },
+            transform: Transform::from_translation(position + Vec3::Z * 0.1),
+            ..default()
+        })
+        .insert(HpBarFill);
+}
+
+fn show_hp_bars(
+    units: Query<(&AttackReceive, &Children), Changed<AttackReceive>>,
+    mut fills: Query<&mut Sprite, With<HpBarFill>>,
+) {
+    for (receive, children) in units.iter() {
+        for &child in children.iter() {
+            if let Ok(mut sprite) = fills.get_mut(child) {
+                sprite.custom_size = Some(hp_bar_size(receive));
+            }
+        }
+    }
+}
+
+fn show_guard_indicators(
+    units: Query<(&AttackReceive, &Children), Changed<AttackReceive>>,
+    mut indicators: Query<&mut Visibility, With<GuardIndicator>>,
+) {
+    for (receive, children) in units.iter() {
+        for &child in children.iter() {
+            if let Ok(mut visibility) = indicators.get_mut(child) {
+                visibility.is_visible = receive.guarding;
+            }
+        }
+    }
+}
+
+/// Team phase mode only, in initiative mode the `TurnQueue` decides.
+fn check_all_acted(
+    mode: Res<TurnMode>,
+    player_units: Query<&AttackSend, (With<Player>, Without<KnockedOut>)>,
+    mut phase: ResMut<State<CombatPhases>>,
+) {
+    if *mode == TurnMode::TeamPhase && player_units.iter().all(|s| s.used) {
+        let _ = phase.overwrite_set(CombatPhases::Enemy);
+    }
+}
+
+#[allow(clippy::type_complexity)]
 fn check_all_dead(
-    player_units: Query<&AttackSend, (With<Player>, Without<Enemy>)>,
+    player_units: Query<&AttackSend, (With<Player>, Without<Enemy>, Without<KnockedOut>)>,
     enemy_units: Query<&AttackSend, (With<Enemy>, Without<Player>)>,
     mut phase: ResMut<State<CombatPhases>>,
 ) {
-    if player_units.iter().len() == 0 && phase.overwrite_set(CombatPhases::EnemyWins).is_ok() {}
-    else if enemy_units.iter().len() == 0 && phase.overwrite_set(CombatPhases::PlayerWins).is_ok(){}
+    let winner = if player_units.is_empty() {
+        CombatPhases::EnemyWins
+    } else if enemy_units.is_empty() {
+        CombatPhases::PlayerWins
+    } else {
+        return;
+    };
+    let _ = phase.overwrite_set(winner);
+}
+
+/// Hands out the encounter's rewards, rolling each item drop once. Every
+/// member still standing gets the xp of each defeated enemy plus the
+/// encounter's bonus xp.
+#[allow(clippy::too_many_arguments)]
+pub fn award_rewards(
+    current: Res<CurrentEncounter>,
+    encounters: Res<Assets<EncounterAsset>>,
+    items: Res<ItemTable>,
+    curve: Res<LevelCurve>,
+    growth: Res<GrowthTable>,
+    mut rng: ResMut<CombatRng>,
+    mut party: ResMut<Party>,
+    mut inventory: ResMut<Inventory>,
+    mut rewards: ResMut<BattleRewards>,
+) {
+    *rewards = BattleRewards::default();
+    let encounter = match encounters.get(&current.handle) {
+        Some(encounter) => encounter,
+        None => return,
+    };
+    rewards.xp = encounter
+        .enemies
+        .iter()
+        .fold(encounter.rewards.xp, |xp, e| xp.saturating_add(e.unit.xp));
+    for unit in party.members.iter_mut().filter(|u| u.hp > 0) {
+        if gain_xp(unit, rewards.xp, &curve, &growth) > 0 {
+            rewards.level_ups.push((unit.name.clone(), unit.level));
+        }
+    }
+    rewards.gold = encounter.rewards.gold;
+    party.gold += encounter.rewards.gold;
+    for drop in encounter.rewards.items.iter() {
+        let item = match items.get(&drop.id) {
+            Some(item) => item,
+            None => {
+                warn!("{}: unknown item drop '{}'", current.path, drop.id);
+                continue;
+            }
+        };
+        if drop.count > 0 && rng.0.chance(drop.chance) {
+            inventory.add(&drop.id, drop.count);
+            rewards.items.push((item.name.clone(), drop.count));
+        }
+    }
 }
 
-fn end_encounter() {
-    println!("Encounter over");
+/// Ends the active unit's turn, which counts down a player unit's statuses.
+/// Enemies count theirs down in `do_enemy_turn`.
+fn remove_active_unit(
+    mut commands: Commands,
+    mut active: Query<(Entity, &mut StatusEffects, Option<&Player>), With<Active>>,
+) {
+    if let Some((a, mut statuses, player)) = active.iter_mut().next() {
+        if player.is_some() {
+            statuses.turn_end();
+        }
+        commands.entity(a).remove::<Active>();
+    }
 }
 
-fn player_wins() {
-    println!("Player wins");
+/// Where the mouse cursor points in the world, if it is over the window the
+/// camera renders to.
+fn cursor_world_position(
+    windows: &Windows,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Option<Vec3> {
+    let wnd = if let RenderTarget::Window(id) = camera.target {
+        windows.get(id)?
+    } else {
+        windows.get_primary()?
+    };
+    let screen_pos = wnd.cursor_position()?;
+    let window_size = Vec2::new(wnd.width(), wnd.height());
+    let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    let mut world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
+    world_pos.z = 1.0;
+    Some(world_pos)
 }
 
-fn enemy_wins() {
-    println!("Enemy wins");
+#[allow(clippy::type_complexity)]
+fn inspect_units(
+    windows: Res<Windows>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    enemies: Query<(Entity, &Transform), (With<Enemy>, With<AttackReceive>)>,
+    cursor: Res<SelectionCursor>,
+    phase: Res<State<CombatPhases>>,
+    mut inspected: ResMut<Inspected>,
+) {
+    let hovered = q_camera
+        .get_single()
+        .ok()
+        .and_then(|(camera, camera_transform)| {
+            let world_pos = cursor_world_position(&windows, camera, camera_transform)?;
+            enemies
+                .iter()
+                .find(|(_e, t)| t.translation.distance(world_pos) <= 32.0)
+                .map(|(e, _t)| e)
+        });
+    let targeted = cursor
+        .0
+        .filter(|c| *phase.current() == CombatPhases::SelectTarget && enemies.get(*c).is_ok());
+    let next = hovered.or(targeted);
+    if inspected.0 != next {
+        inspected.0 = next;
+    }
 }
 
-fn remove_active_unit(mut commands: Commands, active: Query<Entity, With<Active>>) {
-    if let Some(a) = active.iter().next() {
-        commands.entity(a).remove::<Active>();
+/// Whether the player's `kind` can be aimed at a unit on `team` that is
+/// `knocked_out`.
+fn valid_target(
+    kind: &ActionKind,
+    items: &ItemTable,
+    skills: &SkillTable,
+    team: Team,
+    knocked_out: bool,
+) -> bool {
+    match kind {
+        ActionKind::Attack => team == Team::Enemy && !knocked_out,
+        ActionKind::Item(id) => match items.get(id) {
+            Some(item) => {
+                let revive = matches!(item.effect, ItemEffect::Revive { .. });
+                team == item.target.team(Team::Player) && knocked_out == revive
+            }
+            None => false,
+        },
+        ActionKind::Skill(id) => match skills.get(id) {
+            Some(skill) => team == skill.target.team(Team::Player) && !knocked_out,
+            None => false,
+        },
+    }
+}
+
+/// Skills aimed at their user need no target, they go off as soon as they
+/// are picked.
+fn target_user(
+    mut combat_event: EventWriter<CombatEvent>,
+    active: Query<Entity, With<Active>>,
+    pending: Res<PendingAction>,
+    skills: Res<SkillTable>,
+    mut phase: ResMut<State<CombatPhases>>,
+) {
+    let id = match &pending.0 {
+        ActionKind::Skill(id) => id,
+        _ => return,
+    };
+    if skills.get(id).is_none_or(|s| s.shape != TargetShape::User) {
+        return;
+    }
+    if let Some(send) = active.iter().next() {
+        combat_event.send(CombatEvent {
+            send,
+            receive: send,
+            kind: pending.0.clone(),
+        });
+        let _ = phase.overwrite_set(CombatPhases::SelectAction);
     }
 }
 
+/// Picks the target of the pending action, either by clicking a unit or by
+/// cycling the highlight over the valid targets and confirming.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn select_target(
     mut combat_event: EventWriter<CombatEvent>,
+    mut controls: Controls,
     windows: Res<Windows>,
     q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
-    enemies: Query<(Entity, &Transform), (With<Enemy>, Without<Active>)>,
-    active: Query<Entity, (With<Active>, Without<Enemy>)>,
+    units: Query<(Entity, &Transform, Option<&Enemy>, Option<&KnockedOut>), With<AttackReceive>>,
+    mut highlight_q: Query<&mut Transform, (With<Highlight>, Without<AttackReceive>)>,
+    mut cursor: ResMut<SelectionCursor>,
+    active: Query<Entity, With<Active>>,
+    pending: Res<PendingAction>,
+    items: Res<ItemTable>,
+    skills: Res<SkillTable>,
     buttons: ResMut<Input<MouseButton>>,
     mut phase: ResMut<State<CombatPhases>>,
 ) {
+    // Left to right, then top to bottom.
+    let mut targets: Vec<(Entity, Vec3)> = units
+        .iter()
+        .filter(|(_e, _t, enemy, ko)| {
+            let team = if enemy.is_some() {
+                Team::Enemy
+            } else {
+                Team::Player
+            };
+            valid_target(&pending.0, &items, &skills, team, ko.is_some())
+        })
+        .map(|(e, t, _enemy, _ko)| (e, t.translation))
+        .collect();
+    targets.sort_by(|a, b| a.1.x.total_cmp(&b.1.x).then(b.1.y.total_cmp(&a.1.y)));
+    if targets.is_empty() {
+        return;
+    }
+
+    let len = targets.len();
+    let mut index = cursor
+        .0
+        .and_then(|c| targets.iter().position(|(e, _t)| *e == c))
+        .unwrap_or(0);
+    if controls.take(InputAction::Up) || controls.take(InputAction::Left) {
+        index = (index + len - 1) % len;
+    }
+    if controls.take(InputAction::Down) || controls.take(InputAction::Right) {
+        index = (index + 1) % len;
+    }
+    cursor.0 = Some(targets[index].0);
+    highlight_q.single_mut().translation = targets[index].1;
+
+    let mut chosen = None;
+    if controls.take(InputAction::Confirm) {
+        chosen = cursor.0;
+    }
     if buttons.just_pressed(MouseButton::Left) {
-        let _window = windows.get_primary().unwrap();
         let (camera, camera_transform) = q_camera.single();
-        let wnd = if let RenderTarget::Window(id) = camera.target {
-            windows.get(id).unwrap()
-        } else {
-            windows.get_primary().unwrap()
-        };
-        if let Some(screen_pos) = wnd.cursor_position() {
-            let window_size = Vec2::new(wnd.width() as f32, wnd.height() as f32);
-            let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
-            let ndc_to_world =
-                camera_transform.compute_matrix() * camera.projection_matrix().inverse();
-            let mut world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
-            world_pos.z = 1.0;
-
-            if let Some((receive, _transform)) = enemies
+        if let Some(world_pos) = cursor_world_position(&windows, camera, camera_transform) {
+            if let Some((e, _t)) = targets
                 .iter()
-                .find(|(_e, t)| t.translation.distance(world_pos) <= 32.0)
+                .find(|(_e, t)| t.distance(world_pos) <= 32.0)
             {
-                if !active.is_empty() {
-                    let send = active.single();
-                    combat_event.send(CombatEvent { send, receive });
-                    if phase.overwrite_set(CombatPhases::SelectAction).is_ok() {}
+                chosen = Some(*e);
+            }
+        }
+    }
+    if let (Some(receive), Some(send)) = (chosen, active.iter().next()) {
+        combat_event.send(CombatEvent {
+            send,
+            receive,
+            kind: pending.0.clone(),
+        });
+        let _ = phase.overwrite_set(CombatPhases::SelectAction);
+    }
+}
+
+/// Puts the highlight back on the acting unit once targeting is over.
+fn return_highlight(
+    mut highlight_q: Query<&mut Transform, (With<Highlight>, Without<Active>)>,
+    active_q: Query<&Transform, (With<Active>, Without<Highlight>)>,
+    mut cursor: ResMut<SelectionCursor>,
+) {
+    cursor.0 = None;
+    if let Some(active) = active_q.iter().next() {
+        highlight_q.single_mut().translation = active.translation;
+    }
+}
+
+/// Party members are knocked out, anything else is removed from the field.
+fn knock_out(
+    commands: &mut Commands,
+    players: &Query<(), With<Player>>,
+    sprites: &mut Query<&mut Sprite>,
+    entity: Entity,
+) {
+    if players.get(entity).is_ok() {
+        commands.entity(entity).insert(KnockedOut);
+        if let Ok(mut sprite) = sprites.get_mut(entity) {
+            sprite.color = KNOCKED_OUT_COLOR;
+        }
+    } else {
+        fade_out(commands, entity);
+    }
+}
+
+fn fade_out(commands: &mut Commands, entity: Entity) {
+    commands
+        .entity(entity)
+        .remove_bundle::<(
+            AttackSend,
+            AttackReceive,
+            Stats,
+            StatusEffects,
+            EnemyAi,
+            EncounterSlot,
+            Active,
+        )>()
+        .insert(Dying::new());
+}
+
+fn revive(commands: &mut Commands, sprites: &mut Query<&mut Sprite>, entity: Entity) {
+    commands.entity(entity).remove::<KnockedOut>();
+    if let Ok(mut sprite) = sprites.get_mut(entity) {
+        sprite.color = Color::WHITE;
+    }
+}
+
+type FieldUnit = (
+    Entity,
+    Option<&'static Player>,
+    &'static mut AttackSend,
+    &'static mut AttackReceive,
+    &'static mut StatusEffects,
+    &'static mut Stats,
+    Option<&'static mut EnemyAi>,
+);
+
+/// Every unit on the field and the rules of the fight, handed to the
+/// headless `Battle` to start turns and resolve actions.
+#[derive(SystemParam)]
+struct Field<'w, 's> {
+    units: Query<'w, 's, FieldUnit>,
+    damage_table: Res<'w, DamageTable>,
+    items: Res<'w, ItemTable>,
+    skills: Res<'w, SkillTable>,
+    statuses: Res<'w, StatusTable>,
+    rng: ResMut<'w, CombatRng>,
+}
+
+impl<'w, 's> Field<'w, 's> {
+    /// Runs `play` on the field as a `Battle`, where `entities[i]` is the
+    /// entity of `battle.units[i]`, then copies back the components it
+    /// changed.
+    fn play<T>(&mut self, play: impl FnOnce(&mut Battle, &[Entity]) -> T) -> T {
+        let (entities, units): (Vec<Entity>, Vec<Combatant>) = self
+            .units
+            .iter()
+            .map(|(entity, player, send, receive, statuses, stats, ai)| {
+                let unit = Combatant {
+                    team: if player.is_some() {
+                        Team::Player
+                    } else {
+                        Team::Enemy
+                    },
+                    send: send.clone(),
+                    receive: receive.clone(),
+                    statuses: statuses.clone(),
+                    stats: stats.clone(),
+                    ai: ai.cloned().unwrap_or_default(),
+                };
+                (entity, unit)
+            })
+            .unzip();
+        let rules = Rules {
+            damage: &self.damage_table,
+            items: &self.items,
+            skills: &self.skills,
+            statuses: &self.statuses,
+        };
+        let mut battle = Battle::new(units, rules, &mut self.rng.0);
+        let result = play(&mut battle, &entities);
+        for (entity, unit) in entities.into_iter().zip(battle.units) {
+            if let Ok((_e, _p, send, receive, statuses, stats, ai)) = self.units.get_mut(entity) {
+                set_if_changed(send, unit.send);
+                set_if_changed(receive, unit.receive);
+                set_if_changed(statuses, unit.statuses);
+                set_if_changed(stats, unit.stats);
+                if let Some(ai) = ai {
+                    set_if_changed(ai, unit.ai);
                 }
             }
         }
+        result
     }
 }
 
+/// Leaves the component, and its change detection, alone if `value` is
+/// what it already holds.
+fn set_if_changed<T: Component + PartialEq>(mut component: Mut<T>, value: T) {
+    if *component != value {
+        *component = value;
+    }
+}
+
+/// Resolves every queued `CombatEvent` in the order it was sent through the
+/// `Battle` rules and reports each result as a `CombatResultEvent`. Units
+/// knocked out by an earlier event in the same frame neither act nor get
+/// hit by the later ones.
+///
+/// Player and enemy actions share this one system, and so one event cursor:
+/// an action sent right before the enemy phase starts is resolved once.
+#[allow(clippy::too_many_arguments)]
 fn read_events(
     mut combat_events: EventReader<CombatEvent>,
-    mut attacks: Query<(Entity, &mut AttackSend, &mut AttackReceive)>,
+    mut results: EventWriter<CombatResultEvent>,
+    mut field: Field,
+    players: Query<(), With<Player>>,
+    mut sprites: Query<&mut Sprite>,
+    mut inventory: ResMut<Inventory>,
     mut commands: Commands,
     mut phase: ResMut<State<CombatPhases>>,
 ) {
-    let mut sender: Option<Mut<AttackSend>> = Option::None;
-    let mut receiver: Option<(Entity, Mut<AttackReceive>)> = Option::None;
-    if let Some(event) = combat_events.iter().next() {
-        for attack in attacks.iter_mut() {
-            if attack.0 == event.send {
-                sender = Some(attack.1);
-            } else if attack.0 == event.receive {
-                receiver = Some((attack.0, attack.2));
+    if !matches!(
+        phase.current(),
+        CombatPhases::SelectAction | CombatPhases::Enemy
+    ) {
+        return;
+    }
+    let mut any = false;
+    for event in combat_events.iter() {
+        any = true;
+        if let ActionKind::Item(id) = &event.kind {
+            if inventory.count(id) == 0 {
+                continue;
             }
         }
-    }
-    if let Some(mut s) = sender {
-        if let Some(mut r) = receiver {
-            let mut final_dmg = s.dmg;
-            if r.1.resistances.contains(&s.dmg_type) {
-                final_dmg /= 2;
-            } else if r.1.resistances.contains(&s.dmg_type) {
-                final_dmg *= 2;
+        let played = field.play(|battle, entities| {
+            let index = |entity| entities.iter().position(|e| *e == entity);
+            let rules = battle.rules;
+            let (send, receive) = (index(event.send)?, index(event.receive)?);
+            if !battle.units[send].is_alive() {
+                return None;
             }
-            r.1.hp = std::cmp::max(0, r.1.hp - final_dmg);
-            println!("dmg: {}", final_dmg);
-            println!("hp remaining: {}", r.1.hp);
-            if r.1.hp == 0 {
-                commands.entity(r.0).despawn_recursive();
-                println!("dead");
+            let action = match &event.kind {
+                ActionKind::Attack => Action::Attack { send, receive },
+                ActionKind::Item(id) => Action::UseItem {
+                    user: send,
+                    target: receive,
+                    item: rules.items.items.iter().position(|i| &i.id == id)?,
+                },
+                ActionKind::Skill(id) => Action::UseSkill {
+                    user: send,
+                    target: receive,
+                    skill: rules.skills.skills.iter().position(|s| &s.id == id)?,
+                },
+            };
+            let standing: Vec<bool> = battle.units.iter().map(Combatant::is_alive).collect();
+            let outcome = battle.resolve_action(action);
+            // Units knocked out or revived by the action.
+            let turned: Vec<(Entity, bool)> = battle
+                .units
+                .iter()
+                .zip(standing)
+                .zip(entities)
+                .filter(|((unit, was_alive), _e)| unit.is_alive() != *was_alive)
+                .map(|((unit, _was_alive), e)| (*e, unit.is_alive()))
+                .collect();
+            Some((outcome, turned, entities.to_vec()))
+        });
+        let (outcome, turned, entities) = match played {
+            Some(played) => played,
+            None => continue,
+        };
+        for (entity, alive) in turned {
+            if alive {
+                revive(&mut commands, &mut sprites, entity);
+            } else {
+                knock_out(&mut commands, &players, &mut sprites, entity);
+            }
+        }
+
+        let report = CombatResultEvent::new(event);
+        let outcome = match outcome {
+            Some(outcome) => outcome,
+            None => {
+                // An item that would do nothing is kept, but still reported.
+                // A skill its user cannot pay for, or whose target is gone,
+                // fails.
+                match event.kind {
+                    ActionKind::Item(_) => results.send(report),
+                    ActionKind::Skill(_) => results.send(report.failed()),
+                    ActionKind::Attack => {}
+                }
+                continue;
+            }
+        };
+        if let Some(attack) = &outcome.attack {
+            results.send(report.clone().with_attack(attack));
+        }
+        if let (ActionKind::Item(id), Some(used)) = (&event.kind, &outcome.item) {
+            inventory.take(id);
+            if let Some(item) = field.items.get(id) {
+                results.send(report.clone().with_item(item, used));
             }
-            s.used = true;
-            if phase.overwrite_set(CombatPhases::SelectActive).is_ok() {}
         }
+        for (target, skill) in outcome.skill.iter() {
+            let report = report.clone().aimed_at(entities[*target]);
+            if skill.missed {
+                results.send(report.missed());
+                continue;
+            }
+            for h in skill.hits.iter() {
+                results.send(report.clone().with_attack(h));
+            }
+            if skill.healed > 0 {
+                results.send(report.with_heal(skill.healed, skill.hp_left));
+            }
+        }
+    }
+    // Enemy actions are paced by `do_enemy_turn`, which ends its phase itself.
+    if any && *phase.current() != CombatPhases::Enemy {
+        let _ = phase.overwrite_set(CombatPhases::SelectActive);
     }
 }
 
@@ -250,84 +1380,877 @@ fn clear_acted(mut sends: Query<&mut AttackSend>) {
     }
 }
 
+/// Lines up the enemies that act this phase: all of them in formation order,
+/// or only the active one in initiative mode. The first acts right away.
+fn queue_enemy_turns(
+    mode: Res<TurnMode>,
+    enemies: Query<(Entity, &EncounterSlot, Option<&Active>), With<Enemy>>,
+    mut queue: ResMut<EnemyQueue>,
+) {
+    let mut acting: Vec<(usize, Entity)> = enemies
+        .iter()
+        .filter(|(_e, _slot, active)| *mode == TurnMode::TeamPhase || active.is_some())
+        .map(|(e, slot, _active)| (slot.0, e))
+        .collect();
+    acting.sort();
+    queue.pending = acting.into_iter().map(|(_slot, e)| e).collect();
+    queue.acting = None;
+    queue.timer = Timer::from_seconds(0.0, false);
+}
+
+/// How an enemy's turn started in `do_enemy_turn`.
+enum EnemyTurn {
+    /// Damage over time knocked it out.
+    KnockedOut,
+    /// A status cost it the turn, or it is gone.
+    Skipped,
+    /// It acted, through a `CombatEvent` unless it defended.
+    Acted(Option<CombatEvent>),
+}
+
+/// Plays out the next enemy in the `EnemyQueue` once the previous one has
+/// had `EnemyTurnDelay` on screen, which ends the previous one's turn. The
+/// highlight moves to the enemy, its statuses run and may cost it the turn
+/// or its life, then its `EnemyAi` picks an action through the `Battle`.
+/// Control goes back to the player once the queue is empty.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn do_enemy_turn(
-    enemies: Query<Entity, (With<Enemy>, Without<Player>)>,
-    players: Query<Entity, (With<Player>, Without<Enemy>)>,
+    mut commands: Commands,
+    time: Res<Time>,
+    delay: Res<EnemyTurnDelay>,
+    mut queue: ResMut<EnemyQueue>,
+    enemies: Query<&Transform, (With<Enemy>, With<EnemyAi>, Without<Highlight>)>,
+    mut highlight_q: Query<&mut Transform, (With<Highlight>, Without<AttackReceive>)>,
+    mut field: Field,
     mut combat_event: EventWriter<CombatEvent>,
+    mut status_damage: EventWriter<StatusDamageEvent>,
+    mut phase: ResMut<State<CombatPhases>>,
+) {
+    queue.timer.tick(time.delta());
+    if !queue.timer.finished() {
+        return;
+    }
+    if let Some(done) = queue.acting.take() {
+        field.play(|battle, entities| {
+            if let Some(unit) = entities.iter().position(|e| *e == done) {
+                battle.end_turn(unit);
+            }
+        });
+    }
+    let (send, origin) = loop {
+        let send = match queue.pending.pop_front() {
+            Some(send) => send,
+            None => {
+                let _ = phase.overwrite_set(CombatPhases::SelectActive);
+                return;
+            }
+        };
+        if let Ok(transform) = enemies.get(send) {
+            break (send, transform.translation);
+        }
+    };
+    queue.timer = Timer::from_seconds(delay.0, false);
+    highlight_q.single_mut().translation = origin;
+
+    let mut ticked = None;
+    let turn = field.play(|battle, entities| {
+        let user = match entities.iter().position(|e| *e == send) {
+            Some(user) if battle.units[user].is_alive() => user,
+            _ => return EnemyTurn::Skipped,
+        };
+        let start = battle.start_turn(user);
+        ticked = StatusDamageEvent::new(send, &start, battle.units[user].receive.hp);
+        if !battle.units[user].is_alive() {
+            return EnemyTurn::KnockedOut;
+        }
+        if start.skip {
+            return EnemyTurn::Skipped;
+        }
+        let event = match battle.enemy_action(user) {
+            Some(Action::Attack { receive, .. }) => Some(CombatEvent {
+                send,
+                receive: entities[receive],
+                kind: ActionKind::Attack,
+            }),
+            Some(Action::UseSkill { target, skill, .. }) => Some(CombatEvent {
+                send,
+                receive: entities[target],
+                kind: ActionKind::Skill(battle.rules.skills.skills[skill].id.clone()),
+            }),
+            Some(action) => {
+                battle.resolve_action(action);
+                None
+            }
+            None => None,
+        };
+        EnemyTurn::Acted(event)
+    });
+    if let Some(event) = ticked {
+        status_damage.send(event);
+    }
+    match turn {
+        EnemyTurn::KnockedOut => fade_out(&mut commands, send),
+        EnemyTurn::Skipped => {}
+        EnemyTurn::Acted(event) => {
+            queue.acting = Some(send);
+            if let Some(event) = event {
+                combat_event.send(event);
+                commands
+                    .entity(send)
+                    .insert(AttackAnimation::new(origin, -1.0));
+            }
+        }
+    }
+}
+
+/// Moves lunging units out and back, then puts them where they started.
+fn animate_attacks(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut units: Query<(Entity, &mut AttackAnimation, &mut Transform)>,
+) {
+    for (entity, mut animation, mut transform) in units.iter_mut() {
+        animation.timer.tick(time.delta());
+        let progress = animation.timer.percent();
+        let offset = (progress * std::f32::consts::PI).sin() * ATTACK_LUNGE;
+        transform.translation = animation.origin + Vec3::X * offset * animation.direction;
+        if animation.timer.finished() {
+            transform.translation = animation.origin;
+            commands.entity(entity).remove::<AttackAnimation>();
+        }
+    }
+}
+
+/// Pops a number over whoever took each result or status damage, staggered
+/// upwards when one unit is hit several times at once, and flashes units
+/// that lost hp.
+fn show_combat_results(
+    mut commands: Commands,
+    mut results: EventReader<CombatResultEvent>,
+    mut status_damage: EventReader<StatusDamageEvent>,
+    asset_server: Res<AssetServer>,
+    units: Query<(&Transform, Option<&HitFlash>, Option<&AttackAnimation>)>,
+) {
+    // Who the number goes over, its text and colour, and whether they lost hp.
+    let mut numbers: Vec<(Entity, String, Color, bool)> = Vec::new();
+    for result in results.iter() {
+        let hit = if result.reflected || result.failed {
+            result.attacker
+        } else {
+            result.target
+        };
+        let (text, color) = if result.failed {
+            ("failed".to_string(), MISS_COLOR)
+        } else if result.missed {
+            ("miss".to_string(), MISS_COLOR)
+        } else if result.healed > 0 {
+            (format!("+{}", result.healed), HEAL_COLOR)
+        } else {
+            match result.affinity {
+                Affinity::Immune => ("immune".to_string(), RESIST_COLOR),
+                Affinity::Absorb => (format!("+{}", result.final_dmg), HEAL_COLOR),
+                Affinity::Weak => (result.final_dmg.to_string(), WEAK_COLOR),
+                Affinity::Resist => (result.final_dmg.to_string(), RESIST_COLOR),
+                _ if result.final_dmg == 0 => continue,
+                _ => (result.final_dmg.to_string(), DAMAGE_COLOR),
+            }
+        };
+        let damaged = !result.missed
+            && !result.failed
+            && result.final_dmg > 0
+            && !matches!(result.affinity, Affinity::Absorb | Affinity::Immune);
+        numbers.push((hit, text, color, damaged));
+    }
+    numbers.extend(
+        status_damage
+            .iter()
+            .map(|e| (e.unit, e.damage.to_string(), STATUS_DAMAGE_COLOR, true)),
+    );
+
+    let mut shown: Vec<Entity> = Vec::new();
+    for (hit, text, color, damaged) in numbers {
+        let (transform, flash, lunge) = match units.get(hit) {
+            Ok(unit) => unit,
+            Err(_) => continue,
+        };
+        let stack = shown.iter().filter(|e| **e == hit).count() as f32;
+        shown.push(hit);
+        let origin = match (flash, lunge) {
+            (Some(flash), _) => flash.origin,
+            (None, Some(lunge)) => lunge.origin,
+            (None, None) => transform.translation,
+        };
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::from_section(
+                    text,
+                    TextStyle {
+                        font: asset_server.load("fonts/SourceCodePro.ttf"),
+                        font_size: 24.0,
+                        color,
+                    },
+                )
+                .with_alignment(TextAlignment::CENTER),
+                transform: Transform::from_translation(
+                    origin + Vec3::new(0.0, 50.0 + 24.0 * stack, 5.0),
+                ),
+                ..default()
+            })
+            .insert(DamageNumber {
+                timer: Timer::from_seconds(0.8, false),
+            });
+        if damaged {
+            commands.entity(hit).insert(HitFlash::new(origin));
+        }
+    }
+}
+
+fn float_damage_numbers(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut numbers: Query<(Entity, &mut DamageNumber, &mut Text, &mut Transform)>,
+) {
+    for (entity, mut number, mut text, mut transform) in numbers.iter_mut() {
+        number.timer.tick(time.delta());
+        transform.translation.y += DAMAGE_NUMBER_RISE * time.delta_seconds();
+        for section in text.sections.iter_mut() {
+            section.style.color.set_a(1.0 - number.timer.percent());
+        }
+        if number.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Tints hit units red and shakes them, then puts them back. Units in the
+/// middle of a lunge finish it first.
+#[allow(clippy::type_complexity)]
+fn animate_hits(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut units: Query<
+        (
+            Entity,
+            &mut HitFlash,
+            &mut Sprite,
+            &mut Transform,
+            Option<&KnockedOut>,
+            Option<&Dying>,
+        ),
+        Without<AttackAnimation>,
+    >,
+) {
+    for (entity, mut flash, mut sprite, mut transform, knocked_out, dying) in units.iter_mut() {
+        flash.timer.tick(time.delta());
+        let progress = flash.timer.percent();
+        let shake = (progress * std::f32::consts::PI * 6.0).sin() * HIT_SHAKE * (1.0 - progress);
+        transform.translation = flash.origin + Vec3::X * shake;
+        if dying.is_none() {
+            sprite.color = HIT_FLASH_COLOR;
+        }
+        if flash.timer.finished() {
+            transform.translation = flash.origin;
+            if dying.is_none() {
+                sprite.color = if knocked_out.is_some() {
+                    KNOCKED_OUT_COLOR
+                } else {
+                    Color::WHITE
+                };
+            }
+            commands.entity(entity).remove::<HitFlash>();
+        }
+    }
+}
+
+fn fade_dying(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut units: Query<(Entity, &mut Dying, &mut Sprite)>,
+) {
+    for (entity, mut dying, mut sprite) in units.iter_mut() {
+        dying.timer.tick(time.delta());
+        sprite.color = HIT_FLASH_COLOR;
+        sprite.color.set_a(1.0 - dying.timer.percent());
+        if dying.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Everything spawned for a fight, removed together when it ends.
+type BattleEntities = Or<(
+    With<Player>,
+    With<Enemy>,
+    With<Highlight>,
+    With<Background>,
+    With<DamageNumber>,
+)>;
+
+fn despawn_battle(commands: &mut Commands, battle_q: &Query<Entity, BattleEntities>) {
+    for entity in battle_q.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Replaces the running battle, if any, with the last requested encounter.
+fn start_encounter(
+    mut commands: Commands,
+    mut requests: EventReader<EncounterRequest>,
+    asset_server: Res<AssetServer>,
+    battle_q: Query<Entity, BattleEntities>,
+    mut queue: ResMut<TurnQueue>,
     mut phase: ResMut<State<CombatPhases>>,
+    mut view: ResMut<State<Views>>,
 ) {
-    let mut rng = rand::thread_rng();
-    for send in enemies.iter() {
-        if let Some(receive) = players.iter().choose(&mut rng) {
-            combat_event.send(CombatEvent { send, receive });
+    let request = match requests.iter().last() {
+        Some(request) => request,
+        None => return,
+    };
+    despawn_battle(&mut commands, &battle_q);
+    queue.0 = Initiative::default();
+    commands.insert_resource(CurrentEncounter {
+        handle: asset_server.load(request.path.as_str()),
+        path: request.path.clone(),
+        spawned: false,
+        return_to: request.return_to,
+    });
+    let _ = phase.overwrite_set(CombatPhases::Loading);
+    if *view.current() != Views::Combat {
+        let _ = view.overwrite_set(Views::Combat);
+    }
+}
+
+/// After a victory, a confirm press clears the field and hands control back
+/// to the view that requested the encounter.
+fn leave_encounter(
+    mut commands: Commands,
+    mut controls: Controls,
+    current: Res<CurrentEncounter>,
+    battle_q: Query<Entity, BattleEntities>,
+    mut phase: ResMut<State<CombatPhases>>,
+    mut view: ResMut<State<Views>>,
+) {
+    if !controls.take(InputAction::Confirm) {
+        return;
+    }
+    despawn_battle(&mut commands, &battle_q);
+    let _ = phase.overwrite_set(CombatPhases::Inactive);
+    if *view.current() != current.return_to {
+        let _ = view.overwrite_set(current.return_to);
+    }
+}
+
+/// The party keeps the damage taken so far but earns no rewards.
+fn escape_encounter(
+    mut commands: Commands,
+    current: Res<CurrentEncounter>,
+    battle_q: Query<Entity, BattleEntities>,
+    mut phase: ResMut<State<CombatPhases>>,
+    mut view: ResMut<State<Views>>,
+) {
+    despawn_battle(&mut commands, &battle_q);
+    let _ = phase.overwrite_set(CombatPhases::Inactive);
+    if *view.current() != current.return_to {
+        let _ = view.overwrite_set(current.return_to);
+    }
+}
+
+/// Either choice throws away what happened since the last save. Retrying
+/// starts the same encounter over, otherwise the game goes to the title.
+#[allow(clippy::too_many_arguments)]
+fn handle_defeat(
+    mut commands: Commands,
+    mut events: EventReader<DefeatEvent>,
+    current: Res<CurrentEncounter>,
+    battle_q: Query<Entity, BattleEntities>,
+    slots: Res<SaveSlots>,
+    damage_table: Res<DamageTable>,
+    items: Res<ItemTable>,
+    skills: Res<SkillTable>,
+    mut party: ResMut<Party>,
+    mut inventory: ResMut<Inventory>,
+    mut errors: ResMut<LoadErrors>,
+    mut requests: EventWriter<EncounterRequest>,
+    mut phase: ResMut<State<CombatPhases>>,
+    mut view: ResMut<State<Views>>,
+) {
+    let choice = match events.iter().last() {
+        Some(event) => event.choice,
+        None => return,
+    };
+    match load_party(&slots, &damage_table, &items, &skills) {
+        Ok((saved_party, saved_inventory)) => {
+            *party = saved_party;
+            *inventory = saved_inventory;
+        }
+        Err(e) => {
+            errors.0.push(e);
+            return;
+        }
+    }
+    match choice {
+        DefeatChoice::Retry => requests.send(EncounterRequest {
+            path: current.path.clone(),
+            return_to: current.return_to,
+        }),
+        DefeatChoice::Title => {
+            despawn_battle(&mut commands, &battle_q);
+            let _ = phase.overwrite_set(CombatPhases::Inactive);
+            let _ = view.overwrite_set(Views::Title);
         }
     }
-    if phase.overwrite_set(CombatPhases::SelectActive).is_ok() {}
 }
 
-fn spawn_teams(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let players = load_units("assets/players/team.json");
-    spawn_team(&mut commands, &asset_server, players, Player, -300.0);
+#[allow(clippy::too_many_arguments)]
+fn spawn_teams(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    damage_table: Res<DamageTable>,
+    skills: Res<SkillTable>,
+    party: Res<Party>,
+    slots: Res<SaveSlots>,
+    encounters: Res<Assets<EncounterAsset>>,
+    audio: Res<Audio>,
+    mut current: ResMut<CurrentEncounter>,
+    mut mode: ResMut<TurnMode>,
+    mut errors: ResMut<LoadErrors>,
+) {
+    if current.spawned {
+        return;
+    }
+    let encounter = match encounters.get(&current.handle) {
+        Some(encounter) => encounter,
+        None => {
+            if asset_server.get_load_state(&current.handle) == LoadState::Failed {
+                errors.0.push(LoadError::Asset {
+                    path: current.path.clone(),
+                });
+                current.spawned = true;
+            }
+            return;
+        }
+    };
+    if let Err(e) = encounter.validate(&current.path, &damage_table, &skills) {
+        errors.0.push(e);
+        current.spawned = true;
+        return;
+    }
+    current.spawned = true;
+    *mode = encounter.turn_mode;
+
+    if let Some(background) = &encounter.background {
+        commands
+            .spawn_bundle(SpriteBundle {
+                texture: asset_server.load(background.as_str()),
+                ..default()
+            })
+            .insert(Background);
+    }
+    if let Some(music) = &encounter.music {
+        audio.play(asset_server.load(format!("music/{}.ogg", music).as_str()));
+    }
+
+    let (indices, players): (Vec<usize>, Vec<(UnitJson, Vec2)>) = party
+        .members
+        .iter()
+        .enumerate()
+        .map(|(i, u)| (i, (u.clone(), Vec2::new(-300.0, -100.0 + 100.0 * i as f32))))
+        .unzip();
+    let party_path = slots.path(slots.active).display().to_string();
+    let entities = match spawn_team(
+        &mut commands,
+        &asset_server,
+        &damage_table,
+        &party_path,
+        players,
+        Player,
+    ) {
+        Ok(entities) => entities,
+        Err(e) => {
+            errors.0.push(e);
+            return;
+        }
+    };
+    for (entity, index) in entities.into_iter().zip(indices) {
+        commands.entity(entity).insert(PartyMember(index));
+    }
 
-    let enemies = load_units("assets/encounters/3_pawns.json");
-    spawn_team(&mut commands, &asset_server, enemies, Enemy, 300.0);
+    let enemies = encounter
+        .enemies
+        .iter()
+        .map(|e| (e.unit.clone(), encounter.position(e.slot)))
+        .collect();
+    let entities = match spawn_team(
+        &mut commands,
+        &asset_server,
+        &damage_table,
+        &current.path,
+        enemies,
+        Enemy,
+    ) {
+        Ok(entities) => entities,
+        Err(e) => {
+            errors.0.push(e);
+            return;
+        }
+    };
+    for ((index, entity), enemy) in entities.into_iter().enumerate().zip(&encounter.enemies) {
+        commands
+            .entity(entity)
+            .insert(EncounterSlot(index))
+            .insert(EnemyAi::new(enemy.ai.clone()))
+            .with_children(spawn_hp_bar);
+    }
 }
-fn start_encounter(mut phase: ResMut<State<CombatPhases>>, mut view: ResMut<State<Views>>) {
-    view.overwrite_set(Views::Combat).unwrap();
-    phase.overwrite_set(CombatPhases::SelectActive).unwrap();
+
+fn finish_loading(
+    current: Res<CurrentEncounter>,
+    encounters: Res<Assets<EncounterAsset>>,
+    players: Query<(), With<Player>>,
+    mut phase: ResMut<State<CombatPhases>>,
+) {
+    if !current.spawned || players.is_empty() {
+        return;
+    }
+    let has_dialogue = encounters
+        .get(&current.handle)
+        .is_some_and(|e| !e.opening_dialogue.is_empty());
+    let next = if has_dialogue {
+        CombatPhases::Opening
+    } else {
+        CombatPhases::SelectActive
+    };
+    let _ = phase.overwrite_set(next);
+}
+
+/// Applies edits to the current encounter file to the enemies already on the
+/// field. Damage taken and mp spent so far are kept, capped at the new
+/// maxima, and enemies move to their new formation slot.
+#[allow(clippy::type_complexity)]
+fn reload_encounter(
+    mut asset_events: EventReader<AssetEvent<EncounterAsset>>,
+    current: Option<Res<CurrentEncounter>>,
+    encounters: Res<Assets<EncounterAsset>>,
+    damage_table: Res<DamageTable>,
+    skills: Res<SkillTable>,
+    mut enemies: Query<
+        (
+            &EncounterSlot,
+            &mut AttackSend,
+            &mut AttackReceive,
+            &mut Stats,
+            &StatusEffects,
+            &mut EnemyAi,
+            &mut Transform,
+        ),
+        With<Enemy>,
+    >,
+    mut errors: ResMut<LoadErrors>,
+) {
+    let current = match current {
+        Some(current) => current,
+        None => return,
+    };
+    for event in asset_events.iter() {
+        let handle = match event {
+            AssetEvent::Modified { handle } => handle,
+            _ => continue,
+        };
+        if *handle != current.handle {
+            continue;
+        }
+        let encounter = match encounters.get(handle) {
+            Some(encounter) => encounter,
+            None => continue,
+        };
+        if let Err(e) = encounter.validate(&current.path, &damage_table, &skills) {
+            error!("not reloading: {}", e);
+            continue;
+        }
+        for (slot, mut send, mut receive, mut stats, statuses, mut ai, mut transform) in
+            enemies.iter_mut()
+        {
+            if let Some(enemy) = encounter.enemies.get(slot.0) {
+                let fresh = match Combatant::from_json(&enemy.unit, Team::Enemy, &damage_table)
+                {
+                    Ok(fresh) => fresh,
+                    Err(e) => {
+                        errors.0.push(unit_error(&current.path, &enemy.unit, e));
+                        continue;
+                    }
+                };
+                let mut new_receive = fresh.receive;
+                new_receive.hp = std::cmp::min(receive.hp, new_receive.max_hp);
+                new_receive.mp = std::cmp::min(receive.mp, new_receive.max_mp);
+                new_receive.guarding = receive.guarding;
+                new_receive.dmg_taken_multiplier = receive.dmg_taken_multiplier;
+                send.dmg_type = fresh.send.dmg_type;
+                send.skills = fresh.send.skills;
+                *receive = new_receive;
+                stats.base = fresh.stats.base;
+                stats.recalculate(statuses);
+                if ai.profile != enemy.ai {
+                    *ai = EnemyAi::new(enemy.ai.clone());
+                }
+                let position = encounter.position(enemy.slot);
+                transform.translation.x = position.x;
+                transform.translation.y = position.y;
+            }
+        }
+        info!("reloaded {}", current.path);
+    }
 }
 
 impl Plugin for CombatPlugin {
     fn build(&self, app: &mut App) {
-        app.add_state(CombatPhases::SelectAction)
+        app.add_state(CombatPhases::Inactive)
+            .init_resource::<CombatRng>()
+            .init_resource::<PendingAction>()
+            .init_resource::<TurnMode>()
+            .init_resource::<TurnQueue>()
+            .init_resource::<AutoSelect>()
+            .init_resource::<SelectionCursor>()
+            .init_resource::<MenuStack>()
+            .init_resource::<EnemyTurnDelay>()
+            .init_resource::<EnemyQueue>()
+            .init_resource::<Inspected>()
+            .init_resource::<BattleRewards>()
             .add_event::<CombatEvent>()
-            .add_startup_system_to_stage(StartupStage::PreStartup, spawn_teams)
-            .add_startup_system(set_random_active_unit)
-            .add_startup_system_to_stage(StartupStage::PostStartup, spawn_highlight)
-            .add_startup_stage_after(
-                StartupStage::PostStartup,
-                "FinalStartup",
-                SystemStage::parallel(),
+            .add_event::<CombatResultEvent>()
+            .add_event::<StatusDamageEvent>()
+            .add_system(start_encounter)
+            .add_system(reload_encounter)
+            .add_system(show_guard_indicators)
+            .add_system(show_hp_bars)
+            .add_system(sync_status_modifiers)
+            .add_system(clear_knocked_out_statuses)
+            .add_system(show_status_icons)
+            .add_system(sync_turn_queue)
+            .add_system(animate_attacks)
+            .add_system(read_events.after(do_enemy_turn))
+            .add_system(show_combat_results)
+            .add_system(float_damage_numbers)
+            .add_system(animate_hits)
+            .add_system(fade_dying)
+            .add_system_set(
+                SystemSet::on_update(Views::Combat)
+                    .with_system(toggle_auto_select)
+                    .with_system(cancel_menu)
+                    .with_system(inspect_units),
+            )
+            .add_system_set(SystemSet::on_enter(CombatPhases::Loading).with_system(spawn_highlight))
+            .add_system_set(
+                SystemSet::on_update(CombatPhases::Loading)
+                    .with_system(spawn_teams)
+                    .with_system(finish_loading.after(spawn_teams)),
             )
-            .add_startup_system_to_stage("FinalStartup", move_highlight_to_active)
             .add_system_set(
-                SystemSet::on_update(CombatPhases::SelectTarget).with_system(select_target),
+                SystemSet::on_enter(CombatPhases::SelectTarget).with_system(target_user),
+            )
+            .add_system_set(
+                SystemSet::on_update(CombatPhases::SelectTarget)
+                    .with_system(select_target.label(FieldInput)),
+            )
+            .add_system_set(
+                SystemSet::on_exit(CombatPhases::SelectTarget).with_system(return_highlight),
+            )
+            .add_system_set(
+                SystemSet::on_enter(CombatPhases::SelectAction).with_system(clear_menu_stack),
             )
             .add_system_set(
                 SystemSet::on_update(CombatPhases::SelectAction)
                     .with_system(check_all_acted)
                     .with_system(check_all_dead)
-                    .with_system(read_events),
+                    .with_system(defend_active)
+                    .with_system(flee_active),
             )
             .add_system_set(
                 SystemSet::on_enter(CombatPhases::SelectActive)
                     .with_system(remove_active_unit)
-                    .with_system(set_random_active_unit.after(remove_active_unit)),
+                    .with_system(next_in_queue.after(remove_active_unit)),
             )
             .add_system_set(
                 SystemSet::on_update(CombatPhases::SelectActive)
-                    .with_system(move_highlight_to_active)
+                    .with_system(select_active_unit.label(FieldInput))
+                    .with_system(move_highlight_to_active.after(select_active_unit))
                     .with_system(check_all_acted)
                     .with_system(check_all_dead),
             )
-            .add_system_set(SystemSet::on_enter(CombatPhases::Enemy).with_system(toggle_highlight))
+            .add_system_set(SystemSet::on_enter(CombatPhases::Enemy).with_system(queue_enemy_turns))
             .add_system_set(
                 SystemSet::on_update(CombatPhases::Enemy)
                     .with_system(do_enemy_turn)
                     .with_system(check_all_dead),
             )
+            .add_system_set(SystemSet::on_exit(CombatPhases::Enemy).with_system(clear_acted))
+            .add_system_set(
+                SystemSet::on_update(CombatPhases::EnemyWins).with_system(handle_defeat),
+            )
             .add_system_set(
-                SystemSet::on_exit(CombatPhases::Enemy)
-                    .with_system(toggle_highlight)
-                    .with_system(clear_acted),
+                SystemSet::on_enter(CombatPhases::PlayerWins)
+                    .with_system(award_rewards.after(store_party)),
             )
-            .add_system_set(SystemSet::on_enter(CombatPhases::EnemyWins).with_system(end_encounter))
-            .add_system_set(SystemSet::on_update(CombatPhases::EnemyWins).with_system(enemy_wins))
             .add_system_set(
-                SystemSet::on_enter(CombatPhases::PlayerWins).with_system(end_encounter),
+                SystemSet::on_update(CombatPhases::PlayerWins).with_system(leave_encounter),
             )
             .add_system_set(
-                SystemSet::on_update(CombatPhases::PlayerWins).with_system(player_wins),
+                SystemSet::on_enter(CombatPhases::Fled)
+                    .with_system(escape_encounter.after(store_party)),
             );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use bevy::asset::AssetPlugin;
+
+    use crate::controls::InputMap;
+
+    /// Every `CombatResultEvent` sent so far, oldest first.
+    #[derive(Default)]
+    struct Results(Vec<CombatResultEvent>);
+
+    fn record_results(mut events: EventReader<CombatResultEvent>, mut results: ResMut<Results>) {
+        results.0.extend(events.iter().cloned());
+    }
+
+    /// `CombatPlugin` without a window, entering `phase` on the first update.
+    /// Enemies act without a delay and the rng is seeded.
+    fn app(phase: CombatPhases) -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_asset::<EncounterAsset>()
+            .add_event::<EncounterRequest>()
+            .add_event::<CombatButtonEvent>()
+            .add_state(Views::Combat)
+            .insert_resource(Windows::default())
+            .init_resource::<InputMap>()
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<Input<MouseButton>>()
+            .init_resource::<Gamepads>()
+            .init_resource::<Input<GamepadButton>>()
+            .insert_resource(
+                serde_json::from_value::<DamageTable>(json!({
+                    "types": [{ "code": "S", "name": "slashing" }]
+                }))
+                .unwrap(),
+            )
+            .init_resource::<ItemTable>()
+            .init_resource::<SkillTable>()
+            .init_resource::<StatusTable>()
+            .init_resource::<Inventory>()
+            .init_resource::<LoadErrors>()
+            .insert_resource(CurrentEncounter {
+                path: "test".to_string(),
+                handle: Handle::default(),
+                spawned: true,
+                return_to: Views::Overworld,
+            })
+            .insert_resource(CombatRng(BattleRng::from_seed(7)))
+            .insert_resource(EnemyTurnDelay(0.0))
+            .init_resource::<Results>()
+            .add_plugin(CombatPlugin)
+            .add_system(record_results.after(read_events));
+        app.world
+            .resource_mut::<State<CombatPhases>>()
+            .overwrite_replace(phase)
+            .unwrap();
+        app.world
+            .spawn()
+            .insert(Highlight)
+            .insert(Transform::default());
+        app
+    }
+
+    /// A unit with 100 hp hitting for `dmg` slashing.
+    fn spawn_unit<T: Component>(app: &mut App, team: T, dmg: u32) -> Entity {
+        let unit: UnitJson = serde_json::from_value(json!({
+            "name": "unit",
+            "sprite": "unit",
+            "max_hp": 100,
+            "hp": 100,
+            "dmg": dmg,
+            "dmg_type": "S",
+            "weaknesses": [],
+            "resistances": []
+        }))
+        .unwrap();
+        let table = app.world.resource::<DamageTable>();
+        let send = AttackSend::from_json(&unit, table).unwrap();
+        let receive = AttackReceive::from_json(&unit, table).unwrap();
+        app.world
+            .spawn()
+            .insert(team)
+            .insert(send)
+            .insert(receive)
+            .insert(Stats::from_json(&unit))
+            .insert(StatusEffects::default())
+            .insert(Transform::default())
+            .insert(Sprite::default())
+            .id()
+    }
+
+    fn spawn_enemy(app: &mut App, dmg: u32) -> Entity {
+        let enemy = spawn_unit(app, Enemy, dmg);
+        app.world
+            .entity_mut(enemy)
+            .insert(EncounterSlot(0))
+            .insert(EnemyAi::default());
+        enemy
+    }
+
+    fn attack(app: &mut App, send: Entity, receive: Entity) {
+        app.world.send_event(CombatEvent {
+            send,
+            receive,
+            kind: ActionKind::Attack,
+        });
+    }
+
+    fn hp(app: &App, unit: Entity) -> u32 {
+        app.world.get::<AttackReceive>(unit).unwrap().hp
+    }
+
+    #[test]
+    fn every_event_sent_in_one_frame_is_resolved() {
+        let mut app = app(CombatPhases::SelectAction);
+        let players: Vec<Entity> = (0..3).map(|_| spawn_unit(&mut app, Player, 10)).collect();
+        let enemy = spawn_enemy(&mut app, 5);
+        app.update();
+        for &player in players.iter() {
+            attack(&mut app, player, enemy);
+        }
+        app.update();
+
+        let results = &app.world.resource::<Results>().0;
+        let attackers: Vec<Entity> = results.iter().map(|r| r.attacker).collect();
+        assert_eq!(attackers, players);
+        assert!(results.iter().all(|r| r.target == enemy && r.final_dmg > 0));
+        let dealt: u32 = results.iter().map(|r| r.final_dmg).sum();
+        assert_eq!(hp(&app, enemy), 100 - dealt);
+    }
+
+    #[test]
+    fn last_player_action_is_resolved_once_when_the_enemy_phase_starts() {
+        let mut app = app(CombatPhases::SelectAction);
+        let player = spawn_unit(&mut app, Player, 10);
+        let enemy = spawn_enemy(&mut app, 5);
+        app.update();
+        attack(&mut app, player, enemy);
+        for _ in 0..6 {
+            app.update();
+        }
+
+        let results = &app.world.resource::<Results>().0;
+        let by = |unit| results.iter().filter(|r| r.attacker == unit).collect::<Vec<_>>();
+        let (hits, replies) = (by(player), by(enemy));
+        // The enemy phase came and went, and its one attack landed once too.
+        assert_eq!((hits.len(), replies.len()), (1, 1));
+        assert_eq!(hp(&app, enemy), 100 - hits[0].final_dmg);
+        assert_eq!(hp(&app, player), 100 - replies[0].final_dmg);
+    }
+}
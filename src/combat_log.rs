@@ -0,0 +1,386 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    battle::{Affinity, ItemTable, SkillTable},
+    combat::{ActionKind, CombatResultEvent, StatusDamageEvent},
+    controls::{Controls, InputAction},
+    encounter::CurrentEncounter,
+    states::{CombatPhases, Views},
+};
+
+pub struct CombatLogPlugin;
+
+/// Where exported logs are written, one `.txt` and one `.json` per export.
+pub struct CombatLogDir(pub PathBuf);
+
+impl Default for CombatLogDir {
+    fn default() -> Self {
+        CombatLogDir(PathBuf::from("logs"))
+    }
+}
+
+/// The numbers behind a logged `CombatResultEvent`, with units by name.
+#[derive(Debug, Clone, Serialize)]
+pub struct LoggedResult {
+    pub attacker: String,
+    pub target: String,
+    /// `"attack"`, or the id of the skill or item used.
+    pub action: String,
+    pub affinity: Affinity,
+    pub raw_dmg: u32,
+    pub multiplier: f32,
+    pub final_dmg: u32,
+    pub healed: u32,
+    pub hp_left: u32,
+    pub killed: bool,
+    pub revived: bool,
+    pub reflected: bool,
+    pub missed: bool,
+    pub crit: bool,
+    pub failed: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<LoggedResult>,
+}
+
+/// Everything that happened in the running battle, oldest first. Cleared
+/// when the next battle starts loading.
+#[derive(Default)]
+pub struct CombatLog {
+    pub entries: Vec<LogEntry>,
+}
+
+impl CombatLog {
+    pub fn push(&mut self, text: String, result: Option<LoggedResult>) {
+        info!("{}", text);
+        self.entries.push(LogEntry { text, result });
+    }
+}
+
+/// One line for a result, e.g. "Amy attacks pawn 2 for 2 (weak!)".
+fn describe(result: &CombatResultEvent, attacker: &str, target: &str, action: &str) -> String {
+    if result.failed {
+        return format!("{} tries to use {}, but it fails", attacker, action);
+    }
+    let verb = match result.kind {
+        ActionKind::Attack => format!("{} attacks {}", attacker, target),
+        _ => format!("{} uses {} on {}", attacker, action, target),
+    };
+    if result.missed {
+        return format!("{}, but misses", verb);
+    }
+    let mut line = if result.healed > 0 {
+        format!("{}: +{} hp", verb, result.healed)
+    } else {
+        match result.affinity {
+            Affinity::Immune => format!("{}, but it is immune", verb),
+            Affinity::Absorb => format!("{}, but it absorbs {}", verb, result.final_dmg),
+            Affinity::Reflect => {
+                format!("{}, reflected back for {}", verb, result.final_dmg)
+            }
+            Affinity::Weak => format!("{} for {} (weak!)", verb, result.final_dmg),
+            Affinity::Resist => format!("{} for {} (resisted)", verb, result.final_dmg),
+            Affinity::Normal if result.final_dmg > 0 => {
+                format!("{} for {}", verb, result.final_dmg)
+            }
+            Affinity::Normal => format!("{}, to no effect", verb),
+        }
+    };
+    if result.crit {
+        line.push_str(", critical hit!");
+    }
+    if result.revived {
+        line.push_str(&format!(", {} is back on their feet", target));
+    }
+    if result.killed {
+        let fallen = if result.reflected { attacker } else { target };
+        line.push_str(&format!(", {} falls", fallen));
+    }
+    line
+}
+
+fn log_results(
+    mut log: ResMut<CombatLog>,
+    mut results: EventReader<CombatResultEvent>,
+    names: Query<&Name>,
+    skills: Res<SkillTable>,
+    items: Res<ItemTable>,
+) {
+    let name = |entity: Entity| {
+        names
+            .get(entity)
+            .map_or_else(|_| "?".to_string(), |n| n.to_string())
+    };
+    for result in results.iter() {
+        let (id, action) = match &result.kind {
+            ActionKind::Attack => ("attack".to_string(), "attack".to_string()),
+            ActionKind::Skill(id) => (
+                id.clone(),
+                skills
+                    .get(id)
+                    .map_or_else(|| id.clone(), |s| s.name.clone()),
+            ),
+            ActionKind::Item(id) => (
+                id.clone(),
+                items.get(id).map_or_else(|| id.clone(), |i| i.name.clone()),
+            ),
+        };
+        let attacker = name(result.attacker);
+        let target = name(result.target);
+        let text = describe(result, &attacker, &target, &action);
+        log.push(
+            text,
+            Some(LoggedResult {
+                attacker,
+                target,
+                action: id,
+                affinity: result.affinity,
+                raw_dmg: result.raw_dmg,
+                multiplier: result.multiplier,
+                final_dmg: result.final_dmg,
+                healed: result.healed,
+                hp_left: result.hp_left,
+                killed: result.killed,
+                revived: result.revived,
+                reflected: result.reflected,
+                missed: result.missed,
+                crit: result.crit,
+                failed: result.failed,
+            }),
+        );
+    }
+}
+
+fn log_status_damage(
+    mut log: ResMut<CombatLog>,
+    mut status_damage: EventReader<StatusDamageEvent>,
+    names: Query<&Name>,
+) {
+    for event in status_damage.iter() {
+        let name = names
+            .get(event.unit)
+            .map_or_else(|_| "?".to_string(), |n| n.to_string());
+        let mut text = format!("{} takes {} damage from statuses", name, event.damage);
+        if event.killed {
+            text.push_str(&format!(", {} falls", name));
+        }
+        log.push(text, None);
+    }
+}
+
+fn clear_log(mut log: ResMut<CombatLog>) {
+    log.entries.clear();
+}
+
+fn log_victory(mut log: ResMut<CombatLog>) {
+    log.push("The party wins".to_string(), None);
+}
+
+fn log_defeat(mut log: ResMut<CombatLog>) {
+    log.push("The party is defeated".to_string(), None);
+}
+
+#[derive(Serialize)]
+struct LogFile<'a> {
+    encounter: &'a str,
+    entries: &'a [LogEntry],
+}
+
+/// Writes `<stem>.txt` with one line per entry and `<stem>.json` with the
+/// full results.
+pub fn write_log(dir: &Path, stem: &str, encounter: &str, log: &CombatLog) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let text: String = log
+        .entries
+        .iter()
+        .map(|e| format!("{}\n", e.text))
+        .collect();
+    fs::write(dir.join(format!("{}.txt", stem)), text)?;
+    let file = LogFile {
+        encounter,
+        entries: &log.entries,
+    };
+    fs::write(
+        dir.join(format!("{}.json", stem)),
+        serde_json::to_string_pretty(&file)?,
+    )?;
+    Ok(())
+}
+
+fn export_log(
+    mut controls: Controls,
+    log: Res<CombatLog>,
+    dir: Res<CombatLogDir>,
+    current: Option<Res<CurrentEncounter>>,
+) {
+    if !controls.take(InputAction::ExportLog) {
+        return;
+    }
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let stem = format!("battle_{}", secs);
+    let encounter = current.as_ref().map_or("", |c| c.path.as_str());
+    match write_log(&dir.0, &stem, encounter, &log) {
+        Ok(()) => info!("wrote combat log to {}", dir.0.join(&stem).display()),
+        Err(e) => error!("could not write combat log to {}: {}", dir.0.display(), e),
+    }
+}
+
+impl Plugin for CombatLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CombatLog>()
+            .init_resource::<CombatLogDir>()
+            .add_system(log_results)
+            .add_system(log_status_damage)
+            .add_system_set(SystemSet::on_update(Views::Combat).with_system(export_log))
+            .add_system_set(SystemSet::on_enter(CombatPhases::Loading).with_system(clear_log))
+            .add_system_set(SystemSet::on_enter(CombatPhases::PlayerWins).with_system(log_victory))
+            .add_system_set(SystemSet::on_enter(CombatPhases::EnemyWins).with_system(log_defeat));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Amy hitting pawn 2 for 2 with a plain attack.
+    fn hit() -> CombatResultEvent {
+        CombatResultEvent {
+            attacker: Entity::from_raw(1),
+            target: Entity::from_raw(2),
+            kind: ActionKind::Attack,
+            affinity: Affinity::Normal,
+            raw_dmg: 2,
+            multiplier: 1.0,
+            final_dmg: 2,
+            healed: 0,
+            hp_left: 3,
+            killed: false,
+            revived: false,
+            reflected: false,
+            missed: false,
+            crit: false,
+            failed: false,
+        }
+    }
+
+    fn line(result: &CombatResultEvent) -> String {
+        describe(result, "Amy", "pawn 2", "attack")
+    }
+
+    #[test]
+    fn describes_each_affinity() {
+        let with = |affinity, final_dmg| CombatResultEvent {
+            affinity,
+            final_dmg,
+            ..hit()
+        };
+        let cases = [
+            (with(Affinity::Normal, 2), "Amy attacks pawn 2 for 2"),
+            (with(Affinity::Normal, 0), "Amy attacks pawn 2, to no effect"),
+            (with(Affinity::Weak, 4), "Amy attacks pawn 2 for 4 (weak!)"),
+            (with(Affinity::Resist, 1), "Amy attacks pawn 2 for 1 (resisted)"),
+            (with(Affinity::Immune, 0), "Amy attacks pawn 2, but it is immune"),
+            (with(Affinity::Absorb, 2), "Amy attacks pawn 2, but it absorbs 2"),
+            (with(Affinity::Reflect, 2), "Amy attacks pawn 2, reflected back for 2"),
+        ];
+        for (result, expected) in cases {
+            assert_eq!(line(&result), expected);
+        }
+    }
+
+    #[test]
+    fn describes_misses_crits_kills_and_revives() {
+        let missed = CombatResultEvent {
+            missed: true,
+            ..hit()
+        };
+        assert_eq!(line(&missed), "Amy attacks pawn 2, but misses");
+
+        let crit = CombatResultEvent {
+            crit: true,
+            final_dmg: 3,
+            ..hit()
+        };
+        assert_eq!(line(&crit), "Amy attacks pawn 2 for 3, critical hit!");
+
+        let reflect_kill = CombatResultEvent {
+            affinity: Affinity::Reflect,
+            reflected: true,
+            killed: true,
+            ..hit()
+        };
+        assert_eq!(
+            line(&reflect_kill),
+            "Amy attacks pawn 2, reflected back for 2, Amy falls"
+        );
+
+        let revive = CombatResultEvent {
+            kind: ActionKind::Item("phoenix".to_string()),
+            final_dmg: 0,
+            healed: 10,
+            revived: true,
+            ..hit()
+        };
+        assert_eq!(
+            describe(&revive, "Amy", "Billy", "Phoenix Down"),
+            "Amy uses Phoenix Down on Billy: +10 hp, Billy is back on their feet"
+        );
+    }
+
+    #[test]
+    fn write_log_round_trips() {
+        let dir = std::env::temp_dir().join(format!("jrpg_log_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut log = CombatLog::default();
+        let result = hit();
+        log.push(
+            line(&result),
+            Some(LoggedResult {
+                attacker: "Amy".to_string(),
+                target: "pawn 2".to_string(),
+                action: "attack".to_string(),
+                affinity: result.affinity,
+                raw_dmg: result.raw_dmg,
+                multiplier: result.multiplier,
+                final_dmg: result.final_dmg,
+                healed: result.healed,
+                hp_left: result.hp_left,
+                killed: result.killed,
+                revived: result.revived,
+                reflected: result.reflected,
+                missed: result.missed,
+                crit: result.crit,
+                failed: result.failed,
+            }),
+        );
+        log.push("The party wins".to_string(), None);
+
+        write_log(&dir, "battle", "encounters/3_pawns.json", &log).unwrap();
+        let text = fs::read_to_string(dir.join("battle.txt")).unwrap();
+        assert_eq!(text, "Amy attacks pawn 2 for 2\nThe party wins\n");
+        let json: Value =
+            serde_json::from_str(&fs::read_to_string(dir.join("battle.json")).unwrap()).unwrap();
+        assert_eq!(json["encounter"], "encounters/3_pawns.json");
+        let entries = json["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["result"]["final_dmg"], 2);
+        assert_eq!(entries[0]["result"]["affinity"], "normal");
+        assert!(entries[1].get("result").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use std::{collections::BTreeMap, marker::PhantomData};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::save_load::{read_json, LoadError, LoadErrors};
+
+pub struct ControlsPlugin;
+
+/// Player overrides for the default bindings. Actions left out of the file
+/// keep their defaults.
+const CONTROLS_PATH: &str = "assets/config/controls.json";
+
+/// What a key or gamepad button does in menus, unit choice and targeting.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Cancel,
+    ToggleAuto,
+    /// Shows more of the combat log, or less again.
+    ToggleLog,
+    /// Writes the current battle's log to `CombatLogDir`.
+    ExportLog,
+}
+
+/// Keys and gamepad buttons that trigger one `InputAction`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Binding {
+    #[serde(default)]
+    pub keys: Vec<KeyCode>,
+    #[serde(default)]
+    pub gamepad: Vec<GamepadButtonType>,
+}
+
+impl Binding {
+    fn new(keys: &[KeyCode], gamepad: &[GamepadButtonType]) -> Self {
+        Binding {
+            keys: keys.to_vec(),
+            gamepad: gamepad.to_vec(),
+        }
+    }
+}
+
+/// Bindings for every `InputAction`, rebindable through `CONTROLS_PATH`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InputMap {
+    pub bindings: BTreeMap<InputAction, Binding>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use GamepadButtonType as Pad;
+        let bindings = [
+            (
+                InputAction::Up,
+                Binding::new(&[KeyCode::Up, KeyCode::W], &[Pad::DPadUp]),
+            ),
+            (
+                InputAction::Down,
+                Binding::new(&[KeyCode::Down, KeyCode::S], &[Pad::DPadDown]),
+            ),
+            (
+                InputAction::Left,
+                Binding::new(&[KeyCode::Left, KeyCode::A], &[Pad::DPadLeft]),
+            ),
+            (
+                InputAction::Right,
+                Binding::new(&[KeyCode::Right, KeyCode::D], &[Pad::DPadRight]),
+            ),
+            (
+                InputAction::Confirm,
+                Binding::new(&[KeyCode::Return, KeyCode::Space], &[Pad::South]),
+            ),
+            (
+                InputAction::Cancel,
+                Binding::new(&[KeyCode::Escape, KeyCode::Back], &[Pad::East]),
+            ),
+            (
+                InputAction::ToggleAuto,
+                Binding::new(&[KeyCode::Tab], &[Pad::Select]),
+            ),
+            (
+                InputAction::ToggleLog,
+                Binding::new(&[KeyCode::L], &[Pad::North]),
+            ),
+            (InputAction::ExportLog, Binding::new(&[KeyCode::F9], &[])),
+        ];
+        InputMap {
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+impl InputMap {
+    /// The defaults with every binding from `overrides` put in their place.
+    pub fn with_overrides(overrides: InputMap) -> Self {
+        let mut map = InputMap::default();
+        map.bindings.extend(overrides.bindings);
+        map
+    }
+
+    pub fn binding(&self, action: InputAction) -> Option<&Binding> {
+        self.bindings.get(&action)
+    }
+}
+
+/// Label of the systems that pick units on the field. The combat menus take
+/// the same presses and run after them, so the order presses are used up in
+/// is fixed.
+#[derive(SystemLabel, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FieldInput;
+
+/// Reads `InputAction`s through the `InputMap`. A press is used up by the
+/// first system that takes it, so the confirm that picks a unit does not also
+/// pick its action in the phase that follows on the same frame.
+#[derive(SystemParam)]
+pub struct Controls<'w, 's> {
+    map: Res<'w, InputMap>,
+    keys: ResMut<'w, Input<KeyCode>>,
+    gamepads: Res<'w, Gamepads>,
+    buttons: ResMut<'w, Input<GamepadButton>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> Controls<'w, 's> {
+    /// Whether `action` was just pressed, consuming the press.
+    pub fn take(&mut self, action: InputAction) -> bool {
+        let binding = match self.map.binding(action) {
+            Some(binding) => binding,
+            None => return false,
+        };
+        let mut pressed = false;
+        for key in binding.keys.iter() {
+            pressed |= self.keys.clear_just_pressed(*key);
+        }
+        for gamepad in self.gamepads.iter() {
+            for button in binding.gamepad.iter() {
+                pressed |= self
+                    .buttons
+                    .clear_just_pressed(GamepadButton::new(*gamepad, *button));
+            }
+        }
+        pressed
+    }
+}
+
+fn load_input_map(mut commands: Commands, mut errors: ResMut<LoadErrors>) {
+    let map = match read_json(CONTROLS_PATH) {
+        Ok(overrides) => InputMap::with_overrides(overrides),
+        Err(LoadError::MissingFile { .. }) => InputMap::default(),
+        Err(e) => {
+            errors.0.push(e);
+            InputMap::default()
+        }
+    };
+    commands.insert_resource(map);
+}
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system_to_stage(StartupStage::PreStartup, load_input_map);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `take(InputAction::Confirm)` returned on each update.
+    #[derive(Default)]
+    struct Taken(Vec<bool>);
+
+    fn take_confirm(mut controls: Controls, mut taken: ResMut<Taken>) {
+        taken.0.push(controls.take(InputAction::Confirm));
+    }
+
+    #[test]
+    fn take_consumes_a_bound_key_press() {
+        let mut app = App::new();
+        app.add_plugin(ControlsPlugin)
+            .init_resource::<LoadErrors>()
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<Gamepads>()
+            .init_resource::<Input<GamepadButton>>()
+            .init_resource::<Taken>()
+            .add_system(take_confirm);
+        // Startup loads the input map.
+        app.update();
+
+        let key = app
+            .world
+            .resource::<InputMap>()
+            .binding(InputAction::Confirm)
+            .and_then(|binding| binding.keys.first().copied())
+            .expect("confirm is bound to a key");
+        app.world.resource_mut::<Input<KeyCode>>().press(key);
+        app.update();
+        app.update();
+
+        assert_eq!(app.world.resource::<Taken>().0, [false, true, false]);
+    }
+}
@@ -0,0 +1,229 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::{
+    battle::{AiProfile, AiStep, DamageTable, SkillTable},
+    combat::TurnMode,
+    save_load::{LoadError, UnitJson},
+    states::Views,
+};
+
+pub struct EncounterPlugin;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ItemDrop {
+    pub id: String,
+    #[serde(default = "one")]
+    pub count: u32,
+    /// Probability in `0.0..=1.0` that the drop is awarded.
+    #[serde(default = "one_f32")]
+    pub chance: f32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Rewards {
+    #[serde(default)]
+    pub xp: u32,
+    #[serde(default)]
+    pub gold: u32,
+    #[serde(default)]
+    pub items: Vec<ItemDrop>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DialogueLine {
+    pub speaker: String,
+    pub text: String,
+}
+
+/// An enemy, the formation slot it stands in and how it fights.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EncounterUnit {
+    pub slot: usize,
+    #[serde(default)]
+    pub ai: AiProfile,
+    #[serde(flatten)]
+    pub unit: UnitJson,
+}
+
+/// A battle as authored by designers, loaded through the `AssetServer` from
+/// `.json` or `.ron`. `formation` lists the world positions enemies can stand
+/// on; each enemy picks one by index.
+#[derive(Debug, Deserialize, TypeUuid)]
+#[uuid = "8b3d922c-3bc0-4947-9a1d-49a35d373e9f"]
+pub struct EncounterAsset {
+    #[serde(default)]
+    pub background: Option<String>,
+    #[serde(default)]
+    pub music: Option<String>,
+    /// Whether the party can run from this fight with the Flee action.
+    #[serde(default = "yes")]
+    pub flee_allowed: bool,
+    #[serde(default)]
+    pub turn_mode: TurnMode,
+    #[serde(default)]
+    pub rewards: Rewards,
+    #[serde(default)]
+    pub opening_dialogue: Vec<DialogueLine>,
+    pub formation: Vec<[f32; 2]>,
+    pub enemies: Vec<EncounterUnit>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn one_f32() -> f32 {
+    1.0
+}
+
+fn yes() -> bool {
+    true
+}
+
+impl EncounterAsset {
+    pub fn position(&self, slot: usize) -> Vec2 {
+        Vec2::from(self.formation[slot])
+    }
+
+    pub fn validate(
+        &self,
+        path: &str,
+        table: &DamageTable,
+        skills: &SkillTable,
+    ) -> Result<(), LoadError> {
+        let mut taken = vec![false; self.formation.len()];
+        for enemy in self.enemies.iter() {
+            let invalid = |field: &'static str, reason: String| LoadError::Invalid {
+                path: path.to_string(),
+                unit: enemy.unit.name.clone(),
+                field,
+                reason,
+            };
+            enemy
+                .unit
+                .validate(table, skills)
+                .map_err(|(field, reason)| invalid(field, reason))?;
+            match &enemy.ai {
+                AiProfile::Defensive { threshold } if !(0.0..=1.0).contains(threshold) => {
+                    return Err(invalid(
+                        "ai",
+                        format!("threshold {} is outside 0.0..=1.0", threshold),
+                    ))
+                }
+                AiProfile::Scripted { sequence } => {
+                    if sequence.is_empty() {
+                        return Err(invalid("ai", "sequence must not be empty".to_string()));
+                    }
+                    for step in sequence.iter() {
+                        if let AiStep::Skill { id } = step {
+                            if !enemy.unit.skills.contains(id) {
+                                return Err(invalid(
+                                    "ai",
+                                    format!("'{}' is not one of the unit's skills", id),
+                                ));
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+            match taken.get_mut(enemy.slot) {
+                None => {
+                    return Err(invalid(
+                        "slot",
+                        format!(
+                            "{} is outside the formation of {} slots",
+                            enemy.slot,
+                            self.formation.len()
+                        ),
+                    ))
+                }
+                Some(true) => {
+                    return Err(invalid(
+                        "slot",
+                        format!("{} is already taken", enemy.slot),
+                    ))
+                }
+                Some(free) => *free = true,
+            }
+        }
+        for line in self.opening_dialogue.iter() {
+            if line.text.trim().is_empty() {
+                return Err(LoadError::Invalid {
+                    path: path.to_string(),
+                    unit: line.speaker.clone(),
+                    field: "opening_dialogue",
+                    reason: "text must not be empty".to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct EncounterLoader;
+
+impl AssetLoader for EncounterLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let is_ron = load_context
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "ron");
+            let encounter: EncounterAsset = if is_ron {
+                ron::de::from_bytes(bytes)?
+            } else {
+                serde_json::from_slice(bytes)?
+            };
+            load_context.set_default_asset(LoadedAsset::new(encounter));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["json", "ron"]
+    }
+}
+
+/// Index of the enemy's entry in `EncounterAsset::enemies`, used to find it
+/// again when the file is hot reloaded.
+#[derive(Component, Clone, Copy)]
+pub struct EncounterSlot(pub usize);
+
+#[derive(Component)]
+pub struct Background;
+
+/// Asks combat to tear down whatever battle is running and start the
+/// encounter at `path`, an asset path such as `"encounters/3_pawns.json"`.
+/// Once the fight is over the game switches back to `return_to`.
+pub struct EncounterRequest {
+    pub path: String,
+    pub return_to: Views,
+}
+
+/// The encounter the combat view is built from.
+pub struct CurrentEncounter {
+    pub path: String,
+    pub handle: Handle<EncounterAsset>,
+    pub spawned: bool,
+    pub return_to: Views,
+}
+
+impl Plugin for EncounterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<EncounterAsset>()
+            .init_asset_loader::<EncounterLoader>()
+            .add_event::<EncounterRequest>();
+    }
+}
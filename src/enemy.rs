@@ -8,7 +8,6 @@ pub struct EnemyPlugin;
 pub struct Enemy;
 
 fn setup() {}
-fn teardown() {}
 
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
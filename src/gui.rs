@@ -1,35 +1,187 @@
 // pub mod gui;
 
-use bevy::prelude::*;
+use bevy::{input::mouse::MouseWheel, prelude::*};
 
-use crate::states::{Views, CombatPhases};
+use crate::{
+    battle::{Affinity, AttackReceive, AttackSend, DamageTable, ItemTable, SkillTable, Stats},
+    combat::{
+        award_rewards, ActionKind, Active, AutoSelect, BattleRewards, Inspected, MenuStack,
+        PendingAction, TurnMode, TurnQueue,
+    },
+    combat_log::CombatLog,
+    controls::{Controls, FieldInput, InputAction},
+    encounter::{CurrentEncounter, EncounterAsset},
+    player::Player,
+    save_load::{load_party, Inventory, LoadErrors, Party, PartyMember, SaveSlots, SLOT_COUNT},
+    states::{Views, CombatPhases},
+};
 
 pub struct GuiPlugin;
 
 const NORMAL_BUTTON: Color = Color::rgb(0.75, 0.75, 0.75);
 const HOVERED_BUTTON: Color = Color::rgb(0.55, 0.55, 0.55);
 const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.35, 0.35);
+const DISABLED_BUTTON: Color = Color::rgb(0.3, 0.3, 0.3);
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum CombatActions {
     Attack,
+    Skill,
     Defend,
     Item,
+    /// Leaves the fight if the encounter allows fleeing.
+    Flee,
+    /// Toggles `AutoSelect`.
+    Auto,
 }
 
 #[derive(Default)]
 struct CombatButtons {
     attack: Option<Entity>,
+    skill: Option<Entity>,
     defend: Option<Entity>,
     item: Option<Entity>,
+    flee: Option<Entity>,
+    auto: Option<Entity>,
 }
 
+impl CombatButtons {
+    fn slot(&mut self, action: CombatActions) -> &mut Option<Entity> {
+        match action {
+            CombatActions::Attack => &mut self.attack,
+            CombatActions::Skill => &mut self.skill,
+            CombatActions::Defend => &mut self.defend,
+            CombatActions::Item => &mut self.item,
+            CombatActions::Flee => &mut self.flee,
+            CombatActions::Auto => &mut self.auto,
+        }
+    }
+}
+
+#[derive(Component)]
+struct CombatGui;
+
+/// Label of the button toggling `AutoSelect`.
+#[derive(Component)]
+struct AutoSelectText;
+
+#[derive(Component)]
+struct TimelineText;
+
+/// Upcoming turns listed on the timeline, besides the current one.
+const TIMELINE_LENGTH: usize = 7;
+
+/// Column listing the party, one `PartyRow` per member.
+#[derive(Component)]
+struct PartyPanel;
+
+/// Row of the party panel showing the unit it holds.
+#[derive(Component)]
+struct PartyRow(Entity);
+
+/// Filled part of a party member's hp bar.
+#[derive(Component)]
+struct PartyHpFill(Entity);
+
+/// Numeric hp and mp of a party member.
+#[derive(Component)]
+struct PartyStatsText(Entity);
+
+const PARTY_HP_COLOR: Color = Color::rgb(0.3, 0.8, 0.3);
+
+/// Details of the `Inspected` enemy.
+#[derive(Component)]
+struct InspectTooltip;
+
+#[derive(Component)]
+struct CombatLogText;
+
+/// Log lines on screen, and how many of the newest are scrolled past while
+/// the log is expanded.
+#[derive(Default)]
+struct LogView {
+    expanded: bool,
+    scroll: usize,
+}
+
+const LOG_LINES: usize = 4;
+const LOG_LINES_EXPANDED: usize = 20;
+
+/// Item or skill menu, with its entries that can be picked in order.
+#[derive(Component)]
+struct ActionMenu {
+    entries: Vec<Entity>,
+}
+
+/// Action bar button and menu entry the controls point at. The menu entry is
+/// kept while backing out of target selection into the same menu.
+#[derive(Default)]
+struct MenuFocus {
+    bar: usize,
+    menu: usize,
+}
+
+/// Description of the focused item, shown next to the item menu.
+#[derive(Component)]
+struct ItemHint;
+
+/// Entry in the item or skill menu, holds the action it picks.
+#[derive(Component)]
+struct MenuButton(ActionKind);
+
+#[derive(Component)]
+struct LoadErrorScreen;
+
+#[derive(Component)]
+struct DialogueBox;
+
+#[derive(Component)]
+struct DialogueText;
+
+/// Index of the opening dialogue line currently on screen.
+#[derive(Default)]
+struct DialogueProgress(usize);
+
 pub struct CombatButtonEvent {
     pub action: CombatActions,
 }
 
+/// Victory or defeat screen, shown once the HUD is gone.
+#[derive(Component)]
+struct ResultScreen;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DefeatChoice {
+    /// Restore the last save and fight the same encounter again.
+    Retry,
+    /// Restore the last save and go to the title screen.
+    Title,
+}
+
+/// Defeat screen options in the order they are listed.
+const DEFEAT_CHOICES: [(DefeatChoice, &str); 2] = [
+    (DefeatChoice::Retry, "Retry from last save"),
+    (DefeatChoice::Title, "Return to title"),
+];
+
+#[derive(Component)]
+struct DefeatButton(DefeatChoice);
+
+pub struct DefeatEvent {
+    pub choice: DefeatChoice,
+}
+
+#[derive(Component)]
+struct TitleScreen;
+
+/// Title screen line naming the save slot the game starts from.
+#[derive(Component)]
+struct TitleSlot;
+
 fn setup_combat(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut buttons: ResMut<CombatButtons>,
+    auto: Res<AutoSelect>,
 ) {
     // root node
     commands
@@ -44,6 +196,7 @@ fn setup_combat(
             color: Color::NONE.into(),
             ..default()
         })
+        .insert(CombatGui)
         .with_children(|parent| {
             parent
                 .spawn_bundle(NodeBundle {
@@ -80,134 +233,860 @@ fn setup_combat(
                                     ..default()
                                 })
                                 .with_children(|parent| {
-                                    buttons.attack = parent
-                                        .spawn_bundle(ButtonBundle {
-                                            button: Button::default(),
-                                            style: Style {
-                                                size: Size {
-                                                    width: Val::Px(200.0),
-                                                    height: Val::Percent(50.0),
-                                                },
-                                                justify_content: JustifyContent::Center,
-                                                align_items: AlignItems::Center,
-                                                padding: UiRect::new(
-                                                    Val::Px(0.0),
-                                                    Val::Px(0.0),
-                                                    Val::Px(50.0),
-                                                    Val::Px(50.0),
-                                                ),
-                                                ..default()
-                                            },
-                                            ..default()
-                                        })
-                                        .with_children(|parent| {
-                                            parent.spawn_bundle(
-                                                TextBundle::from_section(
-                                                    "Attack",
-                                                    TextStyle {
-                                                        font: asset_server
-                                                            .load("fonts/SourceCodePro.ttf"),
-                                                        font_size: 24.0,
-                                                        color: Color::BLACK,
-                                                    },
-                                                )
-                                                .with_style(Style { ..default() }),
-                                            );
-                                        })
-                                        .id()
-                                        .into();
-                                    buttons.defend = parent
-                                        .spawn_bundle(ButtonBundle {
-                                            button: Button::default(),
-                                            style: Style {
-                                                size: Size {
-                                                    width: Val::Px(200.0),
-                                                    height: Val::Percent(50.0),
-                                                },
-                                                justify_content: JustifyContent::Center,
-                                                align_items: AlignItems::Center,
-                                                padding: UiRect::new(
-                                                    Val::Px(0.0),
-                                                    Val::Px(0.0),
-                                                    Val::Px(50.0),
-                                                    Val::Px(50.0),
-                                                ),
-                                                ..default()
-                                            },
-                                            ..default()
-                                        })
-                                        .with_children(|parent| {
-                                            parent.spawn_bundle(
-                                                TextBundle::from_section(
-                                                    "Defend",
-                                                    TextStyle {
-                                                        font: asset_server
-                                                            .load("fonts/SourceCodePro.ttf"),
-                                                        font_size: 24.0,
-                                                        color: Color::BLACK,
-                                                    },
-                                                )
-                                                .with_style(Style { ..default() }),
-                                            );
-                                        })
-                                        .id()
-                                        .into();
-                                    buttons.item = parent
-                                        .spawn_bundle(ButtonBundle {
-                                            button: Button::default(),
-                                            style: Style {
-                                                size: Size {
-                                                    width: Val::Px(200.0),
-                                                    height: Val::Percent(50.0),
-                                                },
-                                                justify_content: JustifyContent::Center,
-                                                align_items: AlignItems::Center,
-                                                padding: UiRect::new(
-                                                    Val::Px(0.0),
-                                                    Val::Px(0.0),
-                                                    Val::Px(50.0),
-                                                    Val::Px(50.0),
-                                                ),
-                                                ..default()
-                                            },
-                                            ..default()
-                                        })
-                                        .with_children(|parent| {
-                                            parent.spawn_bundle(
-                                                TextBundle::from_section(
-                                                    "Item",
-                                                    TextStyle {
-                                                        font: asset_server
-                                                            .load("fonts/SourceCodePro.ttf"),
-                                                        font_size: 24.0,
-                                                        color: Color::BLACK,
-                                                    },
-                                                )
-                                                .with_style(Style { ..default() }),
-                                            );
-                                        })
-                                        .id()
-                                        .into();
+                                    let font = asset_server.load("fonts/SourceCodePro.ttf");
+                                    for (label, action) in [
+                                        ("Attack", CombatActions::Attack),
+                                        ("Skill", CombatActions::Skill),
+                                        ("Defend", CombatActions::Defend),
+                                        ("Item", CombatActions::Item),
+                                        ("Flee", CombatActions::Flee),
+                                    ] {
+                                        let button =
+                                            spawn_action_button(parent, font.clone(), label);
+                                        *buttons.slot(action) = Some(button);
+                                    }
                                 });
+                            buttons.auto = parent
+                                .spawn_bundle(ButtonBundle {
+                                    button: Button,
+                                    style: Style {
+                                        size: Size {
+                                            width: Val::Px(160.0),
+                                            height: Val::Percent(50.0),
+                                        },
+                                        justify_content: JustifyContent::Center,
+                                        align_items: AlignItems::Center,
+                                        ..default()
+                                    },
+                                    ..default()
+                                })
+                                .with_children(|parent| {
+                                    parent
+                                        .spawn_bundle(TextBundle::from_section(
+                                            auto_label(auto.0),
+                                            TextStyle {
+                                                font: asset_server.load("fonts/SourceCodePro.ttf"),
+                                                font_size: 20.0,
+                                                color: Color::BLACK,
+                                            },
+                                        ))
+                                        .insert(AutoSelectText);
+                                })
+                                .id()
+                                .into();
+                        });
+                });
+        });
+}
+
+/// Button of the action bar, with its label.
+fn spawn_action_button(parent: &mut ChildBuilder, font: Handle<Font>, label: &str) -> Entity {
+    parent
+        .spawn_bundle(ButtonBundle {
+            button: Button,
+            style: Style {
+                size: Size {
+                    width: Val::Px(200.0),
+                    height: Val::Percent(50.0),
+                },
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                padding: UiRect::new(Val::Px(0.0), Val::Px(0.0), Val::Px(50.0), Val::Px(50.0)),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font,
+                    font_size: 24.0,
+                    color: Color::BLACK,
+                },
+            ));
+        })
+        .id()
+}
+
+fn auto_label(auto: bool) -> String {
+    format!("Auto: {}", if auto { "on" } else { "off" })
+}
+
+fn update_auto_label(auto: Res<AutoSelect>, mut text_q: Query<&mut Text, With<AutoSelectText>>) {
+    if !auto.is_changed() {
+        return;
+    }
+    for mut text in text_q.iter_mut() {
+        text.sections[0].value = auto_label(auto.0);
+    }
+}
+
+/// Initiative mode only: a column on the right listing who acts next.
+fn setup_timeline(mut commands: Commands, mode: Res<TurnMode>) {
+    if *mode != TurnMode::Initiative {
+        return;
+    }
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Percent(20.0),
+                    right: Val::Px(8.0),
+                    ..default()
+                },
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            color: Color::rgba(0.15, 0.15, 0.15, 0.9).into(),
+            ..default()
+        })
+        .insert(CombatGui)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_sections(Vec::new()))
+                .insert(TimelineText);
+        });
+}
+
+fn update_timeline(
+    asset_server: Res<AssetServer>,
+    mode: Res<TurnMode>,
+    queue: Res<TurnQueue>,
+    active: Query<Entity, With<Active>>,
+    units: Query<(&Name, Option<&Player>)>,
+    mut text_q: Query<&mut Text, With<TimelineText>>,
+) {
+    if *mode != TurnMode::Initiative {
+        return;
+    }
+    let font = asset_server.load("fonts/SourceCodePro.ttf");
+    let line = |entity: Entity, prefix: &str| {
+        units.get(entity).ok().map(|(name, player)| {
+            let color = if player.is_some() {
+                Color::rgb(0.5, 0.7, 1.0)
+            } else {
+                Color::rgb(1.0, 0.45, 0.4)
+            };
+            TextSection::new(
+                format!("{}{}\n", prefix, name),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 18.0,
+                    color,
+                },
+            )
+        })
+    };
+    let mut sections = vec![TextSection::new(
+        "Turn order\n",
+        TextStyle {
+            font: font.clone(),
+            font_size: 20.0,
+            color: Color::WHITE,
+        },
+    )];
+    sections.extend(active.iter().filter_map(|e| line(e, "> ")));
+    sections.extend(
+        queue
+            .0
+            .preview(TIMELINE_LENGTH)
+            .into_iter()
+            .filter_map(|e| line(e, "  ")),
+    );
+    for mut text in text_q.iter_mut() {
+        text.sections = sections.clone();
+    }
+}
+
+fn setup_party_panel(mut commands: Commands) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(8.0),
+                    left: Val::Px(8.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            color: Color::rgba(0.15, 0.15, 0.15, 0.9).into(),
+            ..default()
+        })
+        .insert(CombatGui)
+        .insert(PartyPanel);
+}
+
+fn hp_percent(receive: &AttackReceive) -> f32 {
+    100.0 * receive.hp as f32 / receive.max_hp.max(1) as f32
+}
+
+fn party_stats(receive: &AttackReceive) -> String {
+    format!(
+        "HP {}/{}  MP {}/{}",
+        receive.hp, receive.max_hp, receive.mp, receive.max_mp
+    )
+}
+
+/// Gives every party member on the field a row in the party panel, in
+/// party order.
+fn add_party_rows(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    panel: Query<Entity, With<PartyPanel>>,
+    rows: Query<&PartyRow>,
+    members: Query<(Entity, &PartyMember, &Name, &AttackReceive)>,
+) {
+    let panel = match panel.get_single() {
+        Ok(panel) => panel,
+        Err(_) => return,
+    };
+    let mut missing: Vec<_> = members
+        .iter()
+        .filter(|(e, _m, _n, _r)| !rows.iter().any(|row| row.0 == *e))
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+    missing.sort_by_key(|(_e, member, _n, _r)| member.0);
+    let font = asset_server.load("fonts/SourceCodePro.ttf");
+    let style = TextStyle {
+        font,
+        font_size: 18.0,
+        color: Color::WHITE,
+    };
+    commands.entity(panel).with_children(|parent| {
+        for (entity, _member, name, receive) in missing {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        align_items: AlignItems::Center,
+                        margin: UiRect::new(Val::Px(0.0), Val::Px(0.0), Val::Px(2.0), Val::Px(2.0)),
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .insert(PartyRow(entity))
+                .with_children(|parent| {
+                    parent.spawn_bundle(
+                        TextBundle::from_section(name.as_str(), style.clone()).with_style(Style {
+                            size: Size::new(Val::Px(120.0), Val::Auto),
+                            ..default()
+                        }),
+                    );
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Px(120.0), Val::Px(12.0)),
+                                ..default()
+                            },
+                            color: Color::rgb(0.05, 0.05, 0.05).into(),
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            parent
+                                .spawn_bundle(NodeBundle {
+                                    style: Style {
+                                        size: Size::new(
+                                            Val::Percent(hp_percent(receive)),
+                                            Val::Percent(100.0),
+                                        ),
+                                        ..default()
+                                    },
+                                    color: PARTY_HP_COLOR.into(),
+                                    ..default()
+                                })
+                                .insert(PartyHpFill(entity));
                         });
+                    parent
+                        .spawn_bundle(
+                            TextBundle::from_section(party_stats(receive), style.clone())
+                                .with_style(Style {
+                                    margin: UiRect::new(
+                                        Val::Px(8.0),
+                                        Val::Px(0.0),
+                                        Val::Px(0.0),
+                                        Val::Px(0.0),
+                                    ),
+                                    ..default()
+                                }),
+                        )
+                        .insert(PartyStatsText(entity));
+                });
+        }
+    });
+}
+
+fn update_party_panel(
+    units: Query<&AttackReceive, (With<Player>, Changed<AttackReceive>)>,
+    mut fills: Query<(&PartyHpFill, &mut Style)>,
+    mut texts: Query<(&PartyStatsText, &mut Text)>,
+) {
+    for (fill, mut style) in fills.iter_mut() {
+        if let Ok(receive) = units.get(fill.0) {
+            style.size.width = Val::Percent(hp_percent(receive));
+        }
+    }
+    for (stats, mut text) in texts.iter_mut() {
+        if let Ok(receive) = units.get(stats.0) {
+            text.sections[0].value = party_stats(receive);
+        }
+    }
+}
+
+/// Rebuilds the tooltip whenever the `Inspected` enemy changes or is hit.
+#[allow(clippy::type_complexity)]
+fn show_inspect_tooltip(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    inspected: Res<Inspected>,
+    units: Query<(&Name, &AttackReceive, &Stats)>,
+    changed: Query<(), Or<(Changed<AttackReceive>, Changed<Stats>)>>,
+    tooltip: Query<Entity, With<InspectTooltip>>,
+) {
+    if !inspected.is_changed() && inspected.0.is_none_or(|e| changed.get(e).is_err()) {
+        return;
+    }
+    for entity in tooltip.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let (name, receive, stats) = match inspected.0.and_then(|e| units.get(e).ok()) {
+        Some(unit) => unit,
+        None => return,
+    };
+    let stats = &stats.current;
+    let mut lines = vec![
+        name.to_string(),
+        format!("HP {}/{}", receive.hp, receive.max_hp),
+        format!(
+            "ATK {}  DEF {}  MAG {}  SPD {}",
+            stats.attack, stats.defense, stats.magic, stats.speed
+        ),
+        format!(
+            "ACC {}  EVA {}  LCK {}",
+            stats.accuracy, stats.evasion, stats.luck
+        ),
+    ];
+    for (label, affinity) in [
+        ("Weak", Affinity::Weak),
+        ("Resists", Affinity::Resist),
+        ("Immune", Affinity::Immune),
+        ("Absorbs", Affinity::Absorb),
+        ("Reflects", Affinity::Reflect),
+    ] {
+        let mut types: Vec<&str> = receive
+            .affinities
+            .iter()
+            .filter(|(_t, a)| **a == affinity)
+            .map(|(t, _a)| t.0.as_str())
+            .collect();
+        if !types.is_empty() {
+            types.sort_unstable();
+            lines.push(format!("{}: {}", label, types.join(", ")));
+        }
+    }
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(8.0),
+                    left: Val::Percent(40.0),
+                    ..default()
+                },
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            color: Color::rgba(0.15, 0.15, 0.15, 0.9).into(),
+            ..default()
+        })
+        .insert(CombatGui)
+        .insert(InspectTooltip)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle::from_section(
+                lines.join("\n"),
+                TextStyle {
+                    font: asset_server.load("fonts/SourceCodePro.ttf"),
+                    font_size: 18.0,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+}
+
+fn setup_combat_log(mut commands: Commands) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Percent(19.0),
+                    right: Val::Px(8.0),
+                    ..default()
+                },
+                size: Size::new(Val::Px(480.0), Val::Auto),
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            color: Color::rgba(0.15, 0.15, 0.15, 0.8).into(),
+            ..default()
+        })
+        .insert(CombatGui)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_sections(Vec::new()))
+                .insert(CombatLogText);
+        });
+}
+
+fn toggle_log_view(mut controls: Controls, mut view: ResMut<LogView>) {
+    if controls.take(InputAction::ToggleLog) {
+        view.expanded = !view.expanded;
+        view.scroll = 0;
+    }
+}
+
+/// The mouse wheel scrolls back through an expanded log.
+fn scroll_log(mut wheel: EventReader<MouseWheel>, log: Res<CombatLog>, mut view: ResMut<LogView>) {
+    let max = log.entries.len().saturating_sub(LOG_LINES_EXPANDED);
+    for event in wheel.iter() {
+        if !view.expanded {
+            continue;
+        }
+        if event.y > 0.0 {
+            view.scroll = (view.scroll + 1).min(max);
+        } else if event.y < 0.0 {
+            view.scroll = view.scroll.saturating_sub(1);
+        }
+    }
+}
+
+fn update_log_view(
+    asset_server: Res<AssetServer>,
+    log: Res<CombatLog>,
+    view: Res<LogView>,
+    mut text_q: Query<&mut Text, With<CombatLogText>>,
+) {
+    if !log.is_changed() && !view.is_changed() {
+        return;
+    }
+    let lines = if view.expanded {
+        LOG_LINES_EXPANDED
+    } else {
+        LOG_LINES
+    };
+    let end = log.entries.len().saturating_sub(view.scroll);
+    let start = end.saturating_sub(lines);
+    let font = asset_server.load("fonts/SourceCodePro.ttf");
+    let sections: Vec<TextSection> = log.entries[start..end]
+        .iter()
+        .map(|entry| {
+            TextSection::new(
+                format!("{}\n", entry.text),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+            )
+        })
+        .collect();
+    for mut text in text_q.iter_mut() {
+        text.sections = sections.clone();
+    }
+}
+
+fn teardown_combat(mut commands: Commands, gui: Query<Entity, With<CombatGui>>) {
+    for entity in gui.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn setup_load_error(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    errors: Res<LoadErrors>,
+) {
+    let font = asset_server.load("fonts/SourceCodePro.ttf");
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::FlexStart,
+                padding: UiRect::all(Val::Px(16.0)),
+                ..default()
+            },
+            color: Color::rgb(0.15, 0.15, 0.15).into(),
+            ..default()
+        })
+        .insert(LoadErrorScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle::from_section(
+                "Failed to load game data",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 32.0,
+                    color: Color::rgb(0.9, 0.3, 0.3),
+                },
+            ));
+            for error in errors.0.iter() {
+                parent.spawn_bundle(
+                    TextBundle::from_section(
+                        error.to_string(),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 18.0,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_style(Style {
+                        margin: UiRect::new(Val::Px(0.0), Val::Px(0.0), Val::Px(8.0), Val::Px(0.0)),
+                        ..default()
+                    }),
+                );
+            }
+        });
+}
+
+fn spawn_result_screen(
+    commands: &mut Commands,
+    font: Handle<Font>,
+    title: &str,
+    title_color: Color,
+    lines: Vec<String>,
+) -> Entity {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            color: Color::rgba(0.05, 0.05, 0.05, 0.85).into(),
+            ..default()
+        })
+        .insert(ResultScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle::from_section(
+                title,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 48.0,
+                    color: title_color,
+                },
+            ));
+            for line in lines {
+                parent.spawn_bundle(
+                    TextBundle::from_section(
+                        line,
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 24.0,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_style(Style {
+                        margin: UiRect::new(Val::Px(0.0), Val::Px(0.0), Val::Px(8.0), Val::Px(0.0)),
+                        ..default()
+                    }),
+                );
+            }
+        })
+        .id()
+}
+
+fn setup_victory(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    rewards: Res<BattleRewards>,
+    party: Res<Party>,
+) {
+    let mut lines = vec![
+        format!("XP +{}", rewards.xp),
+        format!("Gold +{} ({} total)", rewards.gold, party.gold),
+    ];
+    if rewards.items.is_empty() {
+        lines.push("No items found".to_string());
+    }
+    for (name, count) in rewards.items.iter() {
+        lines.push(format!("Found {} x{}", name, count));
+    }
+    for (name, level) in rewards.level_ups.iter() {
+        lines.push(format!("{} reached level {}!", name, level));
+    }
+    lines.push(String::new());
+    lines.push("Confirm to continue".to_string());
+    spawn_result_screen(
+        &mut commands,
+        asset_server.load("fonts/SourceCodePro.ttf"),
+        "Victory!",
+        Color::rgb(1.0, 0.85, 0.3),
+        lines,
+    );
+}
+
+fn setup_defeat(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font: Handle<Font> = asset_server.load("fonts/SourceCodePro.ttf");
+    let screen = spawn_result_screen(
+        &mut commands,
+        font.clone(),
+        "Defeat",
+        Color::rgb(0.9, 0.3, 0.3),
+        Vec::new(),
+    );
+    commands.entity(screen).with_children(|parent| {
+        for (choice, label) in DEFEAT_CHOICES {
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(320.0), Val::Px(48.0)),
+                        margin: UiRect::new(
+                            Val::Px(0.0),
+                            Val::Px(0.0),
+                            Val::Px(16.0),
+                            Val::Px(0.0),
+                        ),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .insert(DefeatButton(choice))
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle::from_section(
+                        label,
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 24.0,
+                            color: Color::BLACK,
+                        },
+                    ));
                 });
+        }
+    });
+}
+
+/// Up and down move between the defeat options, confirm or a click picks
+/// one.
+fn defeat_button_events(
+    mut buttons: Query<(&Interaction, &DefeatButton, &mut UiColor)>,
+    mut controls: Controls,
+    mut focus: Local<usize>,
+    mut events: EventWriter<DefeatEvent>,
+) {
+    let len = DEFEAT_CHOICES.len();
+    if controls.take(InputAction::Up) {
+        *focus = (*focus + len - 1) % len;
+    }
+    if controls.take(InputAction::Down) {
+        *focus = (*focus + 1) % len;
+    }
+    let focused = DEFEAT_CHOICES[*focus].0;
+    let mut picked = None;
+    if controls.take(InputAction::Confirm) {
+        picked = Some(focused);
+    }
+    for (interaction, button, mut color) in buttons.iter_mut() {
+        *color = match interaction {
+            Interaction::Clicked => {
+                picked = Some(button.0);
+                PRESSED_BUTTON.into()
+            }
+            Interaction::Hovered => HOVERED_BUTTON.into(),
+            Interaction::None if button.0 == focused => HOVERED_BUTTON.into(),
+            Interaction::None => NORMAL_BUTTON.into(),
+        };
+    }
+    if let Some(choice) = picked {
+        events.send(DefeatEvent { choice });
+    }
+}
+
+fn teardown_result_screen(mut commands: Commands, screen: Query<Entity, With<ResultScreen>>) {
+    for entity in screen.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn slot_label(slots: &SaveSlots) -> String {
+    let saved = if slots.path(slots.active).exists() {
+        ""
+    } else {
+        " (new game)"
+    };
+    format!("< Slot {}{} >", slots.active + 1, saved)
+}
+
+fn setup_title(mut commands: Commands, asset_server: Res<AssetServer>, slots: Res<SaveSlots>) {
+    let font: Handle<Font> = asset_server.load("fonts/SourceCodePro.ttf");
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::rgb(0.1, 0.1, 0.15).into(),
+            ..default()
+        })
+        .insert(TitleScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle::from_section(
+                "JRPG",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 64.0,
+                    color: Color::WHITE,
+                },
+            ));
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section(
+                        slot_label(&slots),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 32.0,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_style(Style {
+                        margin: UiRect::new(
+                            Val::Px(0.0),
+                            Val::Px(0.0),
+                            Val::Px(24.0),
+                            Val::Px(0.0),
+                        ),
+                        ..default()
+                    }),
+                )
+                .insert(TitleSlot);
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    "Left/right to pick a save slot, confirm to start",
+                    TextStyle {
+                        font,
+                        font_size: 24.0,
+                        color: Color::rgb(0.75, 0.75, 0.75),
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::new(Val::Px(0.0), Val::Px(0.0), Val::Px(24.0), Val::Px(0.0)),
+                    ..default()
+                }),
+            );
         });
 }
 
-fn teardown_combat() {}
+/// Left and right pick the save slot, confirm loads it and starts the game.
+#[allow(clippy::too_many_arguments)]
+fn title_events(
+    mut controls: Controls,
+    mut slots: ResMut<SaveSlots>,
+    mut labels: Query<&mut Text, With<TitleSlot>>,
+    damage_table: Res<DamageTable>,
+    items: Res<ItemTable>,
+    skills: Res<SkillTable>,
+    mut party: ResMut<Party>,
+    mut inventory: ResMut<Inventory>,
+    mut errors: ResMut<LoadErrors>,
+    mut view: ResMut<State<Views>>,
+) {
+    let picked = slots.active;
+    if controls.take(InputAction::Left) {
+        slots.active = (slots.active + SLOT_COUNT - 1) % SLOT_COUNT;
+    }
+    if controls.take(InputAction::Right) {
+        slots.active = (slots.active + 1) % SLOT_COUNT;
+    }
+    if slots.active != picked {
+        for mut text in labels.iter_mut() {
+            text.sections[0].value = slot_label(&slots);
+        }
+    }
+    if !controls.take(InputAction::Confirm) {
+        return;
+    }
+    match load_party(&slots, &damage_table, &items, &skills) {
+        Ok((saved_party, saved_inventory)) => {
+            *party = saved_party;
+            *inventory = saved_inventory;
+        }
+        Err(e) => {
+            errors.0.push(e);
+            return;
+        }
+    }
+    let _ = view.overwrite_set(Views::Overworld);
+}
+
+fn teardown_title(mut commands: Commands, screen: Query<Entity, With<TitleScreen>>) {
+    for entity in screen.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn teardown_load_error(mut commands: Commands, screen: Query<Entity, With<LoadErrorScreen>>) {
+    for entity in screen.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
 
+/// Mouse clicks and the controls both press buttons on the action bar. Left
+/// and right move the focus between the actions, confirm presses the focused
+/// one.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn combat_button_events(
-    mut buttons_q: Query<(&Interaction, &mut UiColor), (Changed<Interaction>, With<Button>)>,
+    mut buttons_q: ParamSet<(
+        Query<(&Interaction, &mut UiColor), (Changed<Interaction>, With<Button>)>,
+        Query<(&Interaction, &mut UiColor), With<Button>>,
+    )>,
     buttons: Res<CombatButtons>,
+    mut controls: Controls,
+    mut focus: ResMut<MenuFocus>,
+    inventory: Res<Inventory>,
+    active: Query<&AttackSend, With<Active>>,
+    mut pending: ResMut<PendingAction>,
+    mut auto: ResMut<AutoSelect>,
+    mut menus: ResMut<MenuStack>,
     mut phase: ResMut<State<CombatPhases>>,
+    mut button_events: EventWriter<CombatButtonEvent>,
 ) {
-    if let Some(button) = buttons.attack {
-        if let Ok((interaction, mut color)) = buttons_q.get_mut(button) {
+    let actions = [
+        (CombatActions::Attack, buttons.attack),
+        (CombatActions::Skill, buttons.skill),
+        (CombatActions::Defend, buttons.defend),
+        (CombatActions::Item, buttons.item),
+        (CombatActions::Flee, buttons.flee),
+    ];
+    let mut pressed = None;
+    let mut clicked = buttons_q.p0();
+    let bar = actions
+        .into_iter()
+        .chain([(CombatActions::Auto, buttons.auto)]);
+    for (action, button) in bar {
+        let button = match button {
+            Some(button) => button,
+            None => continue,
+        };
+        if let Ok((interaction, mut color)) = clicked.get_mut(button) {
             match interaction {
                 Interaction::Clicked => {
                     *color = PRESSED_BUTTON.into();
-                    if phase.overwrite_set(CombatPhases::SelectTarget).is_ok() {
-                    }
+                    pressed = Some(action);
                 }
                 Interaction::Hovered => {
                     *color = HOVERED_BUTTON.into();
@@ -218,42 +1097,519 @@ fn combat_button_events(
             }
         }
     }
-    if let Some(button) = buttons.defend {
-        if let Ok((interaction, mut color)) = buttons_q.get_mut(button) {
-            match interaction {
-                Interaction::Clicked => {
-                    *color = PRESSED_BUTTON.into();
-                }
-                Interaction::Hovered => {
-                    *color = HOVERED_BUTTON.into();
-                }
-                Interaction::None => {
-                    *color = NORMAL_BUTTON.into();
-                }
+
+    if *phase.current() == CombatPhases::SelectAction {
+        let len = actions.len();
+        if controls.take(InputAction::Left) {
+            focus.bar = (focus.bar + len - 1) % len;
+        }
+        if controls.take(InputAction::Right) {
+            focus.bar = (focus.bar + 1) % len;
+        }
+        if controls.take(InputAction::Confirm) {
+            pressed = Some(actions[focus.bar].0);
+        }
+    }
+    // The mouse colours the buttons it is on, the focus the rest.
+    let mut all = buttons_q.p1();
+    for (i, (_action, button)) in actions.iter().enumerate() {
+        let button = match button {
+            Some(button) => *button,
+            None => continue,
+        };
+        if let Ok((Interaction::None, mut color)) = all.get_mut(button) {
+            *color = if i == focus.bar {
+                HOVERED_BUTTON.into()
+            } else {
+                NORMAL_BUTTON.into()
+            };
+        }
+    }
+
+    match pressed {
+        Some(CombatActions::Attack) => {
+            let choosing = matches!(
+                phase.current(),
+                CombatPhases::SelectAction
+                    | CombatPhases::SelectItem
+                    | CombatPhases::SelectSkill
+                    | CombatPhases::SelectTarget
+            );
+            if choosing {
+                pending.0 = ActionKind::Attack;
+                menus.open(&mut phase, CombatPhases::SelectTarget);
             }
         }
+        Some(CombatActions::Skill) => {
+            let has_skills = active.iter().any(|send| !send.skills.is_empty());
+            if *phase.current() == CombatPhases::SelectAction && has_skills {
+                focus.menu = 0;
+                menus.open(&mut phase, CombatPhases::SelectSkill);
+            }
+        }
+        Some(CombatActions::Defend) if *phase.current() == CombatPhases::SelectAction => {
+            button_events.send(CombatButtonEvent {
+                action: CombatActions::Defend,
+            });
+        }
+        Some(CombatActions::Item)
+            if *phase.current() == CombatPhases::SelectAction && !inventory.is_empty() =>
+        {
+            focus.menu = 0;
+            menus.open(&mut phase, CombatPhases::SelectItem);
+        }
+        Some(CombatActions::Flee) if *phase.current() == CombatPhases::SelectAction => {
+            button_events.send(CombatButtonEvent {
+                action: CombatActions::Flee,
+            });
+        }
+        Some(CombatActions::Auto) => auto.0 = !auto.0,
+        _ => {}
     }
-    if let Some(button) = buttons.item {
-        if let Ok((interaction, mut color)) = buttons_q.get_mut(button) {
-            match interaction {
-                Interaction::Clicked => {
-                    *color = PRESSED_BUTTON.into();
-                }
-                Interaction::Hovered => {
-                    *color = HOVERED_BUTTON.into();
-                }
-                Interaction::None => {
-                    *color = NORMAL_BUTTON.into();
+}
+
+/// Lists actions above the action bar, one button per entry of label and
+/// detail. Entries without an action are greyed out and cannot be picked.
+fn spawn_action_menu(
+    commands: &mut Commands,
+    font: Handle<Font>,
+    entries: Vec<(Option<ActionKind>, String, String)>,
+) {
+    let mut enabled = Vec::new();
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Percent(18.0),
+                    left: Val::Px(400.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: UiRect::all(Val::Px(4.0)),
+                ..default()
+            },
+            color: Color::rgb(0.15, 0.15, 0.15).into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            for (action, label, detail) in entries {
+                let color = if action.is_some() {
+                    NORMAL_BUTTON
+                } else {
+                    DISABLED_BUTTON
+                };
+                let mut button = parent.spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(260.0), Val::Px(32.0)),
+                        justify_content: JustifyContent::SpaceBetween,
+                        align_items: AlignItems::Center,
+                        padding: UiRect::new(
+                            Val::Px(8.0),
+                            Val::Px(8.0),
+                            Val::Px(0.0),
+                            Val::Px(0.0),
+                        ),
+                        margin: UiRect::all(Val::Px(2.0)),
+                        ..default()
+                    },
+                    color: color.into(),
+                    ..default()
+                });
+                if let Some(action) = action {
+                    button.insert(MenuButton(action));
+                    enabled.push(button.id());
                 }
+                button.with_children(|parent| {
+                    for text in [label, detail] {
+                        parent.spawn_bundle(TextBundle::from_section(
+                            text,
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 20.0,
+                                color: Color::BLACK,
+                            },
+                        ));
+                    }
+                });
             }
+        })
+        .insert(ActionMenu { entries: enabled });
+}
+
+fn setup_item_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    inventory: Res<Inventory>,
+    items: Res<ItemTable>,
+) {
+    let entries = inventory
+        .items
+        .iter()
+        .map(|(id, count)| {
+            let name = items.get(id).map_or(id.as_str(), |item| item.name.as_str());
+            (
+                Some(ActionKind::Item(id.clone())),
+                name.to_string(),
+                format!("x{}", count),
+            )
+        })
+        .collect();
+    let font = asset_server.load("fonts/SourceCodePro.ttf");
+    spawn_action_menu(&mut commands, font.clone(), entries);
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font,
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Percent(18.0),
+                    left: Val::Px(680.0),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(ItemHint);
+}
+
+fn update_item_hint(
+    focus: Res<MenuFocus>,
+    items: Res<ItemTable>,
+    menu_q: Query<&ActionMenu>,
+    entries_q: Query<&MenuButton>,
+    mut hint_q: Query<&mut Text, With<ItemHint>>,
+) {
+    let focused = menu_q
+        .iter()
+        .next()
+        .and_then(|menu| menu.entries.get(focus.menu))
+        .and_then(|entry| entries_q.get(*entry).ok());
+    let description = match focused {
+        Some(MenuButton(ActionKind::Item(id))) => {
+            items.get(id).map_or("", |item| item.description.as_str())
+        }
+        _ => "",
+    };
+    for mut text in hint_q.iter_mut() {
+        if text.sections[0].value != description {
+            text.sections[0].value = description.to_string();
         }
     }
 }
+
+/// Skills the active unit cannot pay for are listed but disabled.
+fn setup_skill_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    skills: Res<SkillTable>,
+    active: Query<(&AttackSend, &AttackReceive), With<Active>>,
+) {
+    let (send, receive) = match active.iter().next() {
+        Some(active) => active,
+        None => return,
+    };
+    let entries = send
+        .skills
+        .iter()
+        .filter_map(|id| skills.get(id))
+        .map(|skill| {
+            let action = if receive.mp >= skill.mp_cost {
+                Some(ActionKind::Skill(skill.id.clone()))
+            } else {
+                None
+            };
+            (action, skill.name.clone(), format!("{} MP", skill.mp_cost))
+        })
+        .collect();
+    spawn_action_menu(
+        &mut commands,
+        asset_server.load("fonts/SourceCodePro.ttf"),
+        entries,
+    );
+}
+
+/// Up and down move the focus over the entries that can be picked, confirm
+/// picks the focused one. Clicking an entry picks it directly.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn action_menu_events(
+    mut buttons_q: ParamSet<(
+        Query<(&Interaction, &MenuButton, &mut UiColor), Changed<Interaction>>,
+        Query<(&Interaction, &mut UiColor), With<MenuButton>>,
+    )>,
+    menu_q: Query<&ActionMenu>,
+    entries_q: Query<&MenuButton>,
+    mut controls: Controls,
+    mut focus: ResMut<MenuFocus>,
+    mut pending: ResMut<PendingAction>,
+    mut menus: ResMut<MenuStack>,
+    mut phase: ResMut<State<CombatPhases>>,
+) {
+    let mut picked = None;
+    for (interaction, button, mut color) in buttons_q.p0().iter_mut() {
+        match interaction {
+            Interaction::Clicked => {
+                *color = PRESSED_BUTTON.into();
+                picked = Some(button.0.clone());
+            }
+            Interaction::Hovered => {
+                *color = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                *color = NORMAL_BUTTON.into();
+            }
+        }
+    }
+
+    let entries = match menu_q.iter().next() {
+        Some(menu) => &menu.entries,
+        None => return,
+    };
+    if !entries.is_empty() {
+        let len = entries.len();
+        focus.menu = focus.menu.min(len - 1);
+        if controls.take(InputAction::Up) {
+            focus.menu = (focus.menu + len - 1) % len;
+        }
+        if controls.take(InputAction::Down) {
+            focus.menu = (focus.menu + 1) % len;
+        }
+        if controls.take(InputAction::Confirm) {
+            picked = entries_q
+                .get(entries[focus.menu])
+                .ok()
+                .map(|button| button.0.clone());
+        }
+    }
+    let mut all = buttons_q.p1();
+    for (i, entry) in entries.iter().enumerate() {
+        if let Ok((Interaction::None, mut color)) = all.get_mut(*entry) {
+            *color = if i == focus.menu {
+                HOVERED_BUTTON.into()
+            } else {
+                NORMAL_BUTTON.into()
+            };
+        }
+    }
+
+    if let Some(action) = picked {
+        pending.0 = action;
+        menus.open(&mut phase, CombatPhases::SelectTarget);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn teardown_action_menu(
+    mut commands: Commands,
+    menu: Query<Entity, Or<(With<ActionMenu>, With<ItemHint>)>>,
+) {
+    for entity in menu.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn dialogue_sections(speaker: &str, text: &str, font: Handle<Font>) -> Vec<TextSection> {
+    vec![
+        TextSection::new(
+            format!("{}: ", speaker),
+            TextStyle {
+                font: font.clone(),
+                font_size: 24.0,
+                color: Color::rgb(0.9, 0.8, 0.4),
+            },
+        ),
+        TextSection::new(
+            text,
+            TextStyle {
+                font,
+                font_size: 24.0,
+                color: Color::WHITE,
+            },
+        ),
+    ]
+}
+
+fn setup_dialogue(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    current: Res<CurrentEncounter>,
+    encounters: Res<Assets<EncounterAsset>>,
+    mut progress: ResMut<DialogueProgress>,
+) {
+    progress.0 = 0;
+    let line = match encounters
+        .get(&current.handle)
+        .and_then(|e| e.opening_dialogue.first())
+    {
+        Some(line) => line,
+        None => return,
+    };
+    let font = asset_server.load("fonts/SourceCodePro.ttf");
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(20.0)),
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    bottom: Val::Px(0.0),
+                    left: Val::Px(0.0),
+                    ..default()
+                },
+                padding: UiRect::all(Val::Px(16.0)),
+                ..default()
+            },
+            color: Color::rgba(0.05, 0.05, 0.05, 0.9).into(),
+            ..default()
+        })
+        .insert(DialogueBox)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_sections(dialogue_sections(
+                    &line.speaker,
+                    &line.text,
+                    font,
+                )))
+                .insert(DialogueText);
+        });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn advance_dialogue(
+    mut controls: Controls,
+    mouse: Res<Input<MouseButton>>,
+    asset_server: Res<AssetServer>,
+    current: Res<CurrentEncounter>,
+    encounters: Res<Assets<EncounterAsset>>,
+    mut progress: ResMut<DialogueProgress>,
+    mut text_q: Query<&mut Text, With<DialogueText>>,
+    mut phase: ResMut<State<CombatPhases>>,
+) {
+    if !(controls.take(InputAction::Confirm) || mouse.just_pressed(MouseButton::Left)) {
+        return;
+    }
+    progress.0 += 1;
+    let line = encounters
+        .get(&current.handle)
+        .and_then(|e| e.opening_dialogue.get(progress.0));
+    match line {
+        Some(line) => {
+            for mut text in text_q.iter_mut() {
+                text.sections = dialogue_sections(
+                    &line.speaker,
+                    &line.text,
+                    asset_server.load("fonts/SourceCodePro.ttf"),
+                );
+            }
+        }
+        None => {
+            let _ = phase.overwrite_set(CombatPhases::SelectActive);
+        }
+    }
+}
+
+fn teardown_dialogue(mut commands: Commands, dialogue: Query<Entity, With<DialogueBox>>) {
+    for entity in dialogue.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
 impl Plugin for GuiPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<CombatButtons>()
-            .add_system_set(SystemSet::on_enter(Views::Combat).with_system(setup_combat))
-            .add_system_set(SystemSet::on_update(Views::Combat).with_system(combat_button_events))
-            .add_system_set(SystemSet::on_exit(Views::Combat).with_system(teardown_combat));
+            .init_resource::<DialogueProgress>()
+            .init_resource::<MenuFocus>()
+            .init_resource::<LogView>()
+            .add_event::<CombatButtonEvent>()
+            .add_event::<DefeatEvent>()
+            .add_system_set(
+                SystemSet::on_enter(CombatPhases::Loading)
+                    .with_system(teardown_combat)
+                    .with_system(setup_combat.after(teardown_combat))
+                    .with_system(setup_party_panel.after(teardown_combat))
+                    .with_system(setup_combat_log.after(teardown_combat)),
+            )
+            .add_system_set(
+                SystemSet::on_enter(CombatPhases::Inactive).with_system(teardown_combat),
+            )
+            // The turn mode is known once the encounter has loaded.
+            .add_system_set(SystemSet::on_exit(CombatPhases::Loading).with_system(setup_timeline))
+            .add_system_set(
+                SystemSet::on_update(Views::Combat)
+                    .with_system(combat_button_events.after(FieldInput))
+                    .with_system(update_auto_label)
+                    .with_system(update_timeline)
+                    .with_system(add_party_rows)
+                    .with_system(update_party_panel)
+                    .with_system(show_inspect_tooltip)
+                    .with_system(toggle_log_view)
+                    .with_system(scroll_log)
+                    .with_system(update_log_view),
+            )
+            .add_system_set(
+                SystemSet::on_exit(Views::Combat)
+                    .with_system(teardown_combat)
+                    .with_system(teardown_result_screen),
+            )
+            .add_system_set(
+                SystemSet::on_enter(CombatPhases::PlayerWins)
+                    .with_system(teardown_combat)
+                    .with_system(setup_victory.after(award_rewards)),
+            )
+            .add_system_set(
+                SystemSet::on_exit(CombatPhases::PlayerWins).with_system(teardown_result_screen),
+            )
+            .add_system_set(
+                SystemSet::on_enter(CombatPhases::EnemyWins)
+                    .with_system(teardown_combat)
+                    .with_system(setup_defeat),
+            )
+            .add_system_set(
+                SystemSet::on_update(CombatPhases::EnemyWins).with_system(defeat_button_events),
+            )
+            .add_system_set(
+                SystemSet::on_exit(CombatPhases::EnemyWins).with_system(teardown_result_screen),
+            )
+            .add_system_set(SystemSet::on_enter(Views::Title).with_system(setup_title))
+            .add_system_set(SystemSet::on_update(Views::Title).with_system(title_events))
+            .add_system_set(SystemSet::on_exit(Views::Title).with_system(teardown_title))
+            .add_system_set(
+                SystemSet::on_enter(CombatPhases::SelectItem).with_system(setup_item_menu),
+            )
+            .add_system_set(
+                SystemSet::on_update(CombatPhases::SelectItem)
+                    .with_system(action_menu_events.after(FieldInput))
+                    .with_system(update_item_hint.after(action_menu_events)),
+            )
+            .add_system_set(
+                SystemSet::on_exit(CombatPhases::SelectItem).with_system(teardown_action_menu),
+            )
+            .add_system_set(
+                SystemSet::on_enter(CombatPhases::SelectSkill).with_system(setup_skill_menu),
+            )
+            .add_system_set(
+                SystemSet::on_update(CombatPhases::SelectSkill)
+                    .with_system(action_menu_events.after(FieldInput)),
+            )
+            .add_system_set(
+                SystemSet::on_exit(CombatPhases::SelectSkill).with_system(teardown_action_menu),
+            )
+            .add_system_set(SystemSet::on_enter(CombatPhases::Opening).with_system(setup_dialogue))
+            .add_system_set(
+                SystemSet::on_update(CombatPhases::Opening).with_system(advance_dialogue),
+            )
+            .add_system_set(
+                SystemSet::on_exit(CombatPhases::Opening).with_system(teardown_dialogue),
+            )
+            .add_system_set(SystemSet::on_enter(Views::LoadError).with_system(setup_load_error))
+            .add_system_set(
+                SystemSet::on_exit(Views::LoadError).with_system(teardown_load_error),
+            );
     }
 }
@@ -1,29 +1,45 @@
-use bevy::prelude::*;
+use bevy::{asset::AssetServerSettings, prelude::*};
 use bevy_inspector_egui::WorldInspectorPlugin;
 
+mod battle;
 mod camera;
 mod combat;
+mod combat_log;
+mod controls;
+mod encounter;
 mod enemy;
+mod overworld;
 mod player;
+mod progression;
 mod states;
 mod save_load;
 mod gui;
 
 use crate::{
-    camera::CameraPlugin, combat::CombatPlugin, enemy::EnemyPlugin, player::PlayerPlugin,
-    save_load::SaveLoadPlugin,states::Views, gui::GuiPlugin
+    camera::CameraPlugin, combat::CombatPlugin, combat_log::CombatLogPlugin,
+    controls::ControlsPlugin, encounter::EncounterPlugin, enemy::EnemyPlugin,
+    overworld::OverworldPlugin, player::PlayerPlugin, save_load::SaveLoadPlugin,states::Views,
+    gui::GuiPlugin
 };
 
 fn main() {
     App::new()
-        .add_state(Views::Combat)
+        .add_state(Views::Title)
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: true,
+            ..default()
+        })
         .add_plugins(DefaultPlugins)
         .add_plugin(CameraPlugin)
+        .add_plugin(ControlsPlugin)
         .add_plugin(GuiPlugin)
         .add_plugin(PlayerPlugin)
         .add_plugin(EnemyPlugin)
         .add_plugin(SaveLoadPlugin)
+        .add_plugin(EncounterPlugin)
         .add_plugin(CombatPlugin)
+        .add_plugin(CombatLogPlugin)
+        .add_plugin(OverworldPlugin)
         .add_plugin(WorldInspectorPlugin::new())
         .run();
 }
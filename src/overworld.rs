@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::{
+    battle::BattleRng,
+    camera::CameraTarget,
+    controls::{Controls, InputAction},
+    encounter::EncounterRequest,
+    save_load::{LoadError, LoadErrors},
+    states::Views,
+};
+
+pub struct OverworldPlugin;
+
+const MAP_PATH: &str = "maps/start.map.json";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TileDef {
+    pub color: [f32; 3],
+    #[serde(default)]
+    pub sprite: Option<String>,
+    #[serde(default)]
+    pub solid: bool,
+    /// Name of an entry in `MapAsset::zones` that can start fights here.
+    #[serde(default)]
+    pub zone: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EncounterZone {
+    /// Chance per step of starting a fight, `0.0..=1.0`.
+    pub chance: f32,
+    /// Encounter asset paths, one is picked at random.
+    pub encounters: Vec<String>,
+}
+
+/// A tile map loaded from `.map.json`. `rows` are read top to bottom and
+/// every character is looked up in `legend`.
+#[derive(Debug, Deserialize, TypeUuid)]
+#[uuid = "cce55979-771a-4805-a6c8-717933a1fe7b"]
+pub struct MapAsset {
+    pub tile_size: f32,
+    pub start: [i32; 2],
+    pub rows: Vec<String>,
+    pub legend: HashMap<char, TileDef>,
+    #[serde(default)]
+    pub zones: HashMap<String, EncounterZone>,
+}
+
+impl MapAsset {
+    pub fn tile(&self, pos: IVec2) -> Option<&TileDef> {
+        if pos.x < 0 || pos.y < 0 {
+            return None;
+        }
+        self.rows
+            .get(pos.y as usize)
+            .and_then(|row| row.chars().nth(pos.x as usize))
+            .and_then(|c| self.legend.get(&c))
+    }
+
+    pub fn world_position(&self, pos: IVec2) -> Vec2 {
+        Vec2::new(pos.x as f32, -pos.y as f32) * self.tile_size
+    }
+
+    pub fn validate(&self, path: &str) -> Result<(), LoadError> {
+        let malformed = |reason: String| LoadError::Malformed {
+            path: path.to_string(),
+            reason,
+        };
+        for (y, row) in self.rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let tile = self.legend.get(&c).ok_or_else(|| {
+                    malformed(format!("tile '{}' at {},{} is not in the legend", c, x, y))
+                })?;
+                if let Some(zone) = &tile.zone {
+                    if !self.zones.contains_key(zone) {
+                        return Err(malformed(format!(
+                            "tile '{}' uses unknown zone '{}'",
+                            c, zone
+                        )));
+                    }
+                }
+            }
+        }
+        match self.tile(IVec2::from(self.start)) {
+            Some(tile) if !tile.solid => Ok(()),
+            _ => Err(malformed(format!(
+                "start {:?} is not a walkable tile",
+                self.start
+            ))),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MapLoader;
+
+impl AssetLoader for MapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let map: MapAsset = serde_json::from_slice(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map.json"]
+    }
+}
+
+#[derive(Component)]
+struct MapTile;
+
+#[derive(Component)]
+struct Avatar;
+
+/// The map being explored and where the party stands on it. Kept across
+/// fights so the avatar comes back to the tile it left from.
+pub struct Overworld {
+    pub path: String,
+    pub handle: Handle<MapAsset>,
+    pub position: Option<IVec2>,
+    spawned: bool,
+}
+
+#[derive(Default)]
+pub struct OverworldRng(pub BattleRng);
+
+fn load_map(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Overworld {
+        path: MAP_PATH.to_string(),
+        handle: asset_server.load(MAP_PATH),
+        position: None,
+        spawned: false,
+    });
+}
+
+fn spawn_map(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    maps: Res<Assets<MapAsset>>,
+    mut overworld: ResMut<Overworld>,
+    mut errors: ResMut<LoadErrors>,
+) {
+    if overworld.spawned {
+        return;
+    }
+    let map = match maps.get(&overworld.handle) {
+        Some(map) => map,
+        None => {
+            if asset_server.get_load_state(&overworld.handle) == LoadState::Failed {
+                errors.0.push(LoadError::Asset {
+                    path: overworld.path.clone(),
+                });
+                overworld.spawned = true;
+            }
+            return;
+        }
+    };
+    overworld.spawned = true;
+    if let Err(e) = map.validate(&overworld.path) {
+        errors.0.push(e);
+        return;
+    }
+
+    for (y, row) in map.rows.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            let tile = &map.legend[&c];
+            let position = map.world_position(IVec2::new(x as i32, y as i32));
+            let [r, g, b] = tile.color;
+            let mut bundle = SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb(r, g, b),
+                    custom_size: Some(Vec2::splat(map.tile_size)),
+                    ..default()
+                },
+                transform: Transform::from_translation(position.extend(0.0)),
+                ..default()
+            };
+            if let Some(sprite) = &tile.sprite {
+                bundle.texture = asset_server.load(format!("sprites/{}.png", sprite).as_str());
+            }
+            commands
+                .spawn_bundle(bundle)
+                .insert(MapTile);
+        }
+    }
+
+    let position = *overworld.position.get_or_insert(IVec2::from(map.start));
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("sprites/king.png"),
+            sprite: Sprite {
+                custom_size: Some(Vec2::splat(map.tile_size)),
+                ..default()
+            },
+            transform: Transform::from_translation(map.world_position(position).extend(1.0)),
+            ..default()
+        })
+        .insert(Avatar)
+        .insert(CameraTarget);
+}
+
+/// Steps one tile per direction press. Walking onto a tile with an encounter
+/// zone may start a fight that returns here once it is over.
+fn move_avatar(
+    mut controls: Controls,
+    maps: Res<Assets<MapAsset>>,
+    mut overworld: ResMut<Overworld>,
+    mut rng: ResMut<OverworldRng>,
+    mut avatar_q: Query<&mut Transform, With<Avatar>>,
+    mut requests: EventWriter<EncounterRequest>,
+) {
+    let step = if controls.take(InputAction::Up) {
+        IVec2::new(0, -1)
+    } else if controls.take(InputAction::Down) {
+        IVec2::new(0, 1)
+    } else if controls.take(InputAction::Left) {
+        IVec2::new(-1, 0)
+    } else if controls.take(InputAction::Right) {
+        IVec2::new(1, 0)
+    } else {
+        return;
+    };
+    let (map, position) = match (maps.get(&overworld.handle), overworld.position) {
+        (Some(map), Some(position)) => (map, position),
+        _ => return,
+    };
+    let target = position + step;
+    let tile = match map.tile(target) {
+        Some(tile) if !tile.solid => tile,
+        _ => return,
+    };
+    overworld.position = Some(target);
+    for mut transform in avatar_q.iter_mut() {
+        let world = map.world_position(target);
+        transform.translation.x = world.x;
+        transform.translation.y = world.y;
+    }
+
+    let zone = match tile.zone.as_ref().and_then(|z| map.zones.get(z)) {
+        Some(zone) => zone,
+        None => return,
+    };
+    if rng.0.chance(zone.chance) {
+        if let Some(path) = rng.0.choose(zone.encounters.iter()) {
+            requests.send(EncounterRequest {
+                path: path.clone(),
+                return_to: Views::Overworld,
+            });
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn teardown(
+    mut commands: Commands,
+    mut overworld: ResMut<Overworld>,
+    map_q: Query<Entity, Or<(With<MapTile>, With<Avatar>)>>,
+) {
+    for entity in map_q.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    overworld.spawned = false;
+}
+
+impl Plugin for OverworldPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<MapAsset>()
+            .init_asset_loader::<MapLoader>()
+            .init_resource::<OverworldRng>()
+            .add_startup_system(load_map)
+            .add_system_set(
+                SystemSet::on_update(Views::Overworld)
+                    .with_system(spawn_map)
+                    .with_system(move_avatar),
+            )
+            .add_system_set(SystemSet::on_exit(Views::Overworld).with_system(teardown));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::AssetPlugin;
+    use serde_json::json;
+
+    use super::*;
+    use crate::controls::InputMap;
+
+    /// Every `EncounterRequest` sent so far, by encounter path.
+    #[derive(Default)]
+    struct Requests(Vec<String>);
+
+    fn record_requests(mut events: EventReader<EncounterRequest>, mut seen: ResMut<Requests>) {
+        seen.0.extend(events.iter().map(|r| r.path.clone()));
+    }
+
+    /// `OverworldPlugin` without a window, exploring a corridor walled in on
+    /// all sides that starts on the left and ends in grass where every step
+    /// starts a fight.
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_state(Views::Overworld)
+            .add_event::<EncounterRequest>()
+            .init_resource::<LoadErrors>()
+            .init_resource::<InputMap>()
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<Gamepads>()
+            .init_resource::<Input<GamepadButton>>()
+            .insert_resource(OverworldRng(BattleRng::from_seed(3)))
+            .init_resource::<Requests>()
+            .add_plugin(OverworldPlugin)
+            .add_system(record_requests.after(move_avatar));
+        // Startup loads the real map, the test one replaces it.
+        app.update();
+        let map: MapAsset = serde_json::from_value(json!({
+            "tile_size": 32.0,
+            "start": [1, 1],
+            "rows": ["#####", "#..g#", "#####"],
+            "legend": {
+                "#": { "color": [0.2, 0.2, 0.2], "solid": true },
+                ".": { "color": [0.6, 0.5, 0.3] },
+                "g": { "color": [0.2, 0.7, 0.2], "zone": "grass" }
+            },
+            "zones": {
+                "grass": { "chance": 1.0, "encounters": ["encounters/test.json"] }
+            }
+        }))
+        .unwrap();
+        let handle = app.world.resource_mut::<Assets<MapAsset>>().add(map);
+        app.world.insert_resource(Overworld {
+            path: "test.map.json".to_string(),
+            handle,
+            position: None,
+            spawned: false,
+        });
+        app.update();
+        app
+    }
+
+    fn step(app: &mut App, key: KeyCode) -> Option<IVec2> {
+        app.world.resource_mut::<Input<KeyCode>>().press(key);
+        app.update();
+        app.world.resource_mut::<Input<KeyCode>>().release(key);
+        app.world.resource::<Overworld>().position
+    }
+
+    #[test]
+    fn solid_tiles_block_the_avatar() {
+        let mut app = app();
+        assert_eq!(app.world.resource::<Overworld>().position, Some(IVec2::new(1, 1)));
+        assert_eq!(step(&mut app, KeyCode::Up), Some(IVec2::new(1, 1)));
+        assert_eq!(step(&mut app, KeyCode::Left), Some(IVec2::new(1, 1)));
+        assert_eq!(step(&mut app, KeyCode::Right), Some(IVec2::new(2, 1)));
+        assert_eq!(step(&mut app, KeyCode::Down), Some(IVec2::new(2, 1)));
+    }
+
+    #[test]
+    fn stepping_into_an_encounter_zone_requests_a_fight() {
+        let mut app = app();
+        step(&mut app, KeyCode::Right);
+        assert!(app.world.resource::<Requests>().0.is_empty());
+        assert_eq!(step(&mut app, KeyCode::Right), Some(IVec2::new(3, 1)));
+        assert_eq!(app.world.resource::<Requests>().0, ["encounters/test.json"]);
+    }
+}
@@ -9,8 +9,6 @@ pub struct Player;
 
 fn setup() {}
 
-fn teardown() {}
-
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_startup_system(setup);
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::save_load::UnitJson;
+
+/// Total xp needed for each level, loaded from
+/// `assets/progression/levels.json`. `thresholds[0]` is level 1 and must be
+/// 0; the last entry is the level cap.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LevelCurve {
+    pub thresholds: Vec<u32>,
+}
+
+impl Default for LevelCurve {
+    fn default() -> Self {
+        LevelCurve {
+            thresholds: vec![0],
+        }
+    }
+}
+
+impl LevelCurve {
+    pub fn level_for(&self, xp: u32) -> u32 {
+        self.thresholds.iter().filter(|t| **t <= xp).count().max(1) as u32
+    }
+}
+
+/// What a unit gains per level. Hp and mp gained are also restored.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Growth {
+    #[serde(default)]
+    pub max_hp: u32,
+    #[serde(default)]
+    pub max_mp: u32,
+    #[serde(default)]
+    pub dmg: u32,
+    #[serde(default)]
+    pub speed: u32,
+    #[serde(default)]
+    pub defense: u32,
+    #[serde(default)]
+    pub magic: u32,
+    #[serde(default)]
+    pub accuracy: u32,
+    #[serde(default)]
+    pub evasion: u32,
+    #[serde(default)]
+    pub luck: u32,
+}
+
+/// Growth per party member by `UnitJson::name`, loaded from
+/// `assets/progression/growth.json`. Members without an entry use `default`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GrowthTable {
+    #[serde(default)]
+    pub default: Growth,
+    #[serde(default)]
+    pub characters: HashMap<String, Growth>,
+}
+
+impl GrowthTable {
+    pub fn get(&self, name: &str) -> &Growth {
+        self.characters.get(name).unwrap_or(&self.default)
+    }
+}
+
+/// Adds `xp` to the unit and applies its growth once for every level
+/// gained. Returns the number of levels gained.
+pub fn gain_xp(unit: &mut UnitJson, xp: u32, curve: &LevelCurve, growth: &GrowthTable) -> u32 {
+    unit.xp = unit.xp.saturating_add(xp);
+    let target = curve.level_for(unit.xp);
+    let mut gained = 0;
+    let growth = growth.get(&unit.name);
+    while unit.level < target {
+        unit.level += 1;
+        unit.max_hp += growth.max_hp;
+        unit.hp += growth.max_hp;
+        unit.max_mp += growth.max_mp;
+        unit.mp += growth.max_mp;
+        unit.dmg += growth.dmg;
+        unit.speed += growth.speed;
+        unit.defense += growth.defense;
+        unit.magic += growth.magic;
+        unit.accuracy += growth.accuracy;
+        unit.evasion += growth.evasion;
+        unit.luck += growth.luck;
+        gained += 1;
+    }
+    gained
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn curve() -> LevelCurve {
+        LevelCurve {
+            thresholds: vec![0, 10, 25, 45],
+        }
+    }
+
+    fn growth() -> GrowthTable {
+        serde_json::from_value(json!({
+            "default": { "max_hp": 2 },
+            "characters": {
+                "amy": { "max_hp": 3, "max_mp": 1, "dmg": 1, "speed": 1, "luck": 2 }
+            }
+        }))
+        .unwrap()
+    }
+
+    /// A level 1 unit with 15 of 20 hp and no xp.
+    fn unit(name: &str) -> UnitJson {
+        serde_json::from_value(json!({
+            "name": name,
+            "sprite": name,
+            "max_hp": 20,
+            "hp": 15,
+            "dmg": 5,
+            "dmg_type": "S",
+            "weaknesses": [],
+            "resistances": []
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn one_award_can_gain_several_levels() {
+        let mut amy = unit("amy");
+        assert_eq!(gain_xp(&mut amy, 30, &curve(), &growth()), 2);
+        assert_eq!((amy.level, amy.xp), (3, 30));
+        // Growth applies once per level, and the hp and mp gained are filled.
+        assert_eq!((amy.max_hp, amy.hp), (26, 21));
+        assert_eq!((amy.max_mp, amy.mp), (2, 2));
+        assert_eq!((amy.dmg, amy.speed, amy.luck), (7, 12, 4));
+    }
+
+    #[test]
+    fn levels_stop_at_the_last_threshold() {
+        let mut amy = unit("amy");
+        assert_eq!(gain_xp(&mut amy, 1000, &curve(), &growth()), 3);
+        assert_eq!((amy.level, amy.xp), (4, 1000));
+        assert_eq!(gain_xp(&mut amy, 1000, &curve(), &growth()), 0);
+        assert_eq!((amy.level, amy.xp), (4, 2000));
+    }
+
+    #[test]
+    fn members_without_growth_use_the_default() {
+        let mut billy = unit("billy");
+        assert_eq!(gain_xp(&mut billy, 10, &curve(), &growth()), 1);
+        assert_eq!((billy.level, billy.max_hp, billy.hp, billy.dmg), (2, 22, 17, 5));
+    }
+}
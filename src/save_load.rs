@@ -1,42 +1,915 @@
 // pub mod gui;
 
-use std::{fs::File, io::Read};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
 
 use bevy::prelude::*;
-use serde::{Serialize, Deserialize};
-use serde_json::{from_str};
+use serde::{de::DeserializeOwned, Serialize, Deserialize};
+use serde_json::{from_str, from_value, json, Value};
+
+use crate::{
+    battle::{
+        Affinity, AttackReceive, AttackSend, DamageTable, DamageType, ItemEffect, ItemTable,
+        SkillTable, Stats, StatusTable, TargetShape,
+    },
+    progression::{GrowthTable, LevelCurve},
+    states::{CombatPhases, Views},
+};
 
 pub struct SaveLoadPlugin;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Schema version written into every save file. Fields added since default
+/// when missing, so only a change that breaks older saves needs a new version
+/// and a step in `migrate`.
+pub const SAVE_VERSION: u32 = 1;
+const DEFAULT_TEAM_PATH: &str = "assets/players/team.json";
+const DEFAULT_INVENTORY_PATH: &str = "assets/players/inventory.json";
+const DAMAGE_TABLE_PATH: &str = "assets/combat/damage_types.json";
+const STATUS_TABLE_PATH: &str = "assets/combat/statuses.json";
+const ITEM_TABLE_PATH: &str = "assets/items/items.json";
+const SKILL_TABLE_PATH: &str = "assets/skills/skills.json";
+const LEVEL_CURVE_PATH: &str = "assets/progression/levels.json";
+const GROWTH_TABLE_PATH: &str = "assets/progression/growth.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UnitJson {
     pub name: String,
     pub sprite: String,
     pub max_hp: u32,
     pub hp: u32,
+    /// The unit's attack stat.
     pub dmg: u32,
-    pub dmg_type: char,
-    pub weaknesses: Vec<char>,
-    pub resistances: Vec<char>,
+    pub dmg_type: String,
+    pub weaknesses: Vec<String>,
+    pub resistances: Vec<String>,
+    #[serde(default)]
+    pub immunities: Vec<String>,
+    #[serde(default)]
+    pub absorbs: Vec<String>,
+    #[serde(default)]
+    pub reflects: Vec<String>,
+    #[serde(default)]
+    pub max_mp: u32,
+    #[serde(default)]
+    pub mp: u32,
+    #[serde(default)]
+    pub skills: Vec<String>,
+    #[serde(default = "default_speed")]
+    pub speed: u32,
+    #[serde(default)]
+    pub defense: u32,
+    #[serde(default)]
+    pub magic: u32,
+    #[serde(default)]
+    pub accuracy: u32,
+    #[serde(default)]
+    pub evasion: u32,
+    #[serde(default)]
+    pub luck: u32,
+    #[serde(default = "default_level")]
+    pub level: u32,
+    /// Total xp earned, see `LevelCurve`. For enemies, the xp the party
+    /// shares when it is defeated.
+    #[serde(default)]
+    pub xp: u32,
+}
+
+fn default_speed() -> u32 {
+    10
+}
+
+fn default_level() -> u32 {
+    1
+}
+
+impl UnitJson {
+    /// Returns the offending field and what is wrong with it.
+    pub fn validate(
+        &self,
+        table: &DamageTable,
+        skills: &SkillTable,
+    ) -> Result<(), (&'static str, String)> {
+        if self.name.trim().is_empty() {
+            return Err(("name", "must not be empty".to_string()));
+        }
+        if self.sprite.trim().is_empty() {
+            return Err(("sprite", "must not be empty".to_string()));
+        }
+        if self.max_hp == 0 {
+            return Err(("max_hp", "must be greater than 0".to_string()));
+        }
+        if self.hp > self.max_hp {
+            return Err((
+                "hp",
+                format!("{} is greater than max_hp {}", self.hp, self.max_hp),
+            ));
+        }
+        if self.speed == 0 {
+            return Err(("speed", "must be greater than 0".to_string()));
+        }
+        if self.level == 0 {
+            return Err(("level", "must be greater than 0".to_string()));
+        }
+        if self.mp > self.max_mp {
+            return Err((
+                "mp",
+                format!("{} is greater than max_mp {}", self.mp, self.max_mp),
+            ));
+        }
+        if let Some(id) = self.skills.iter().find(|id| skills.get(id).is_none()) {
+            return Err(("skills", format!("unknown skill '{}'", id)));
+        }
+        table
+            .parse(&self.dmg_type)
+            .map_err(|e| ("dmg_type", e.to_string()))?;
+        let lists = [
+            ("weaknesses", &self.weaknesses),
+            ("resistances", &self.resistances),
+            ("immunities", &self.immunities),
+            ("absorbs", &self.absorbs),
+            ("reflects", &self.reflects),
+        ];
+        for (field, codes) in lists {
+            for code in codes.iter() {
+                table.parse(code).map_err(|e| (field, e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    MissingFile {
+        path: String,
+    },
+    Io {
+        path: String,
+        error: io::Error,
+    },
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    Invalid {
+        path: String,
+        unit: String,
+        field: &'static str,
+        reason: String,
+    },
+    Malformed {
+        path: String,
+        reason: String,
+    },
+    Asset {
+        path: String,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::MissingFile { path } => write!(f, "{}: file not found", path),
+            LoadError::Io { path, error } => write!(f, "{}: {}", path, error),
+            LoadError::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path, line, column, message),
+            LoadError::Invalid {
+                path,
+                unit,
+                field,
+                reason,
+            } => write!(f, "{}: unit '{}', field '{}': {}", path, unit, field, reason),
+            LoadError::Malformed { path, reason } => write!(f, "{}: {}", path, reason),
+            LoadError::Asset { path } => {
+                write!(f, "{}: asset failed to load, see the log for details", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Everything that failed to load so far. Anything in here switches the game
+/// to `Views::LoadError`.
+#[derive(Default)]
+pub struct LoadErrors(pub Vec<LoadError>);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveFile {
+    pub version: u32,
+    pub party: Vec<UnitJson>,
+    #[serde(default)]
+    pub inventory: Inventory,
+    #[serde(default)]
+    pub gold: u32,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    MissingVersion,
+    UnsupportedVersion(u64),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "io error: {}", e),
+            SaveError::Parse(e) => write!(f, "malformed save: {}", e),
+            SaveError::MissingVersion => write!(f, "save has no version"),
+            SaveError::UnsupportedVersion(v) => write!(
+                f,
+                "save version {} is not supported, expected version {}",
+                v, SAVE_VERSION
+            ),
+        }
+    }
+}
+
+impl SaveError {
+    /// The same failure as a `LoadError` naming the save file, for the
+    /// error screen.
+    pub fn load_error(self, path: &Path) -> LoadError {
+        let path = path.display().to_string();
+        match self {
+            SaveError::Io(error) => LoadError::Io { path, error },
+            SaveError::Parse(e) => LoadError::Parse {
+                path,
+                line: e.line(),
+                column: e.column(),
+                message: e.to_string(),
+            },
+            e => LoadError::Malformed {
+                path,
+                reason: e.to_string(),
+            },
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(e: serde_json::Error) -> Self {
+        SaveError::Parse(e)
+    }
 }
 
-fn save_game() {
-    /* save player team to disk */
+/// The player's party between and during fights. Combat spawns from it and
+/// writes back into it, saves serialize it.
+#[derive(Default)]
+pub struct Party {
+    pub members: Vec<UnitJson>,
+    pub gold: u32,
 }
 
-pub fn load_units(asset_path: &str) -> Vec<UnitJson> {
-    let mut file = File::open(asset_path).expect("File und");
+/// Item counts carried by the party, keyed by `ItemDef::id`. Items whose
+/// count drops to 0 are removed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Inventory {
+    pub items: BTreeMap<String, u32>,
+}
+
+impl Inventory {
+    pub fn count(&self, id: &str) -> u32 {
+        self.items.get(id).copied().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, id: &str, count: u32) {
+        if count > 0 {
+            *self.items.entry(id.to_string()).or_insert(0) += count;
+        }
+    }
+
+    /// Removes one `id`, returns false if there was none to take.
+    pub fn take(&mut self, id: &str) -> bool {
+        match self.items.get_mut(id) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                true
+            }
+            Some(_) => {
+                self.items.remove(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+/// Index of the entity's entry in `Party::members`.
+#[derive(Component, Clone, Copy)]
+pub struct PartyMember(pub usize);
+
+/// How many save slots the title screen offers.
+pub const SLOT_COUNT: u8 = 3;
+
+/// One save file per slot under `dir`. `active` is the slot picked on the
+/// title screen, which saving and reloading after a defeat use.
+pub struct SaveSlots {
+    pub dir: PathBuf,
+    pub active: u8,
+}
+
+impl Default for SaveSlots {
+    fn default() -> Self {
+        SaveSlots {
+            dir: PathBuf::from("saves"),
+            active: 0,
+        }
+    }
+}
+
+impl SaveSlots {
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("slot_{}.json", slot))
+    }
+}
+
+pub struct SaveRequest {
+    pub slot: u8,
+}
+
+pub fn read_json<T: DeserializeOwned>(asset_path: &str) -> Result<T, LoadError> {
+    let mut file = File::open(asset_path).map_err(|error| match error.kind() {
+        io::ErrorKind::NotFound => LoadError::MissingFile {
+            path: asset_path.to_string(),
+        },
+        _ => LoadError::Io {
+            path: asset_path.to_string(),
+            error,
+        },
+    })?;
     let mut data = String::new();
     file.read_to_string(&mut data)
-        .expect("Error while reading file");
-    from_str(&data).expect("Error fitting schema")
+        .map_err(|error| LoadError::Io {
+            path: asset_path.to_string(),
+            error,
+        })?;
+    from_str(&data).map_err(|e| LoadError::Parse {
+        path: asset_path.to_string(),
+        line: e.line(),
+        column: e.column(),
+        message: e.to_string(),
+    })
+}
+
+pub fn validate_units(
+    asset_path: &str,
+    units: &[UnitJson],
+    table: &DamageTable,
+    skills: &SkillTable,
+) -> Result<(), LoadError> {
+    for unit in units.iter() {
+        unit.validate(table, skills)
+            .map_err(|(field, reason)| LoadError::Invalid {
+                path: asset_path.to_string(),
+                unit: unit.name.clone(),
+                field,
+                reason,
+            })?;
+    }
+    Ok(())
+}
+
+pub fn load_units(
+    asset_path: &str,
+    table: &DamageTable,
+    skills: &SkillTable,
+) -> Result<Vec<UnitJson>, LoadError> {
+    let units: Vec<UnitJson> = read_json(asset_path)?;
+    validate_units(asset_path, &units, table, skills)?;
+    Ok(units)
+}
+
+pub fn load_damage_table(asset_path: &str) -> Result<DamageTable, LoadError> {
+    let table: DamageTable = read_json(asset_path)?;
+    let formula = &table.formula;
+    for (name, value) in [
+        ("defense_weight", formula.defense_weight),
+        ("variance", formula.variance),
+        ("crit_chance", formula.crit_chance),
+        ("crit_per_luck", formula.crit_per_luck),
+        ("crit_multiplier", formula.crit_multiplier),
+        ("hit_per_point", formula.hit_per_point),
+    ] {
+        if value < 0.0 {
+            return Err(LoadError::Malformed {
+                path: asset_path.to_string(),
+                reason: format!("formula: {} {} must not be negative", name, value),
+            });
+        }
+    }
+    for (name, value) in [
+        ("hit_chance", formula.hit_chance),
+        ("min_hit_chance", formula.min_hit_chance),
+    ] {
+        if !(0.0..=1.0).contains(&value) {
+            return Err(LoadError::Malformed {
+                path: asset_path.to_string(),
+                reason: format!("formula: {} {} is outside 0.0..=1.0", name, value),
+            });
+        }
+    }
+    Ok(table)
+}
+
+pub fn load_status_table(asset_path: &str) -> Result<StatusTable, LoadError> {
+    let statuses: StatusTable = read_json(asset_path)?;
+    let malformed = |reason: String| LoadError::Malformed {
+        path: asset_path.to_string(),
+        reason,
+    };
+    for (i, status) in statuses.statuses.iter().enumerate() {
+        if status.id.trim().is_empty() || status.name.trim().is_empty() {
+            return Err(malformed(format!("status {} needs an id and a name", i)));
+        }
+        if statuses.statuses[..i].iter().any(|other| other.id == status.id) {
+            return Err(malformed(format!("status id '{}' is used twice", status.id)));
+        }
+        if status.duration == 0 {
+            return Err(malformed(format!(
+                "status '{}': duration must be greater than 0",
+                status.id
+            )));
+        }
+        if let Some((stat, scale)) = status.stats.iter().find(|(_stat, scale)| **scale < 0.0) {
+            return Err(malformed(format!(
+                "status '{}': {:?} multiplier {} must not be negative",
+                status.id, stat, scale
+            )));
+        }
+    }
+    Ok(statuses)
+}
+
+pub fn load_level_curve(asset_path: &str) -> Result<LevelCurve, LoadError> {
+    let curve: LevelCurve = read_json(asset_path)?;
+    let malformed = |reason: String| LoadError::Malformed {
+        path: asset_path.to_string(),
+        reason,
+    };
+    if curve.thresholds.first() != Some(&0) {
+        return Err(malformed("thresholds must start at 0".to_string()));
+    }
+    if curve.thresholds.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(malformed(
+            "thresholds must be strictly increasing".to_string(),
+        ));
+    }
+    Ok(curve)
+}
+
+pub fn load_growth_table(asset_path: &str) -> Result<GrowthTable, LoadError> {
+    read_json(asset_path)
+}
+
+fn unknown_status<'a>(ids: &'a [String], statuses: &StatusTable) -> Option<&'a String> {
+    ids.iter().find(|id| statuses.get(id).is_none())
+}
+
+pub fn load_item_table(
+    asset_path: &str,
+    table: &DamageTable,
+    statuses: &StatusTable,
+) -> Result<ItemTable, LoadError> {
+    let items: ItemTable = read_json(asset_path)?;
+    let malformed = |reason: String| LoadError::Malformed {
+        path: asset_path.to_string(),
+        reason,
+    };
+    for (i, item) in items.items.iter().enumerate() {
+        if item.id.trim().is_empty() || item.name.trim().is_empty() {
+            return Err(malformed(format!("item {} needs an id and a name", i)));
+        }
+        if items.items[..i].iter().any(|other| other.id == item.id) {
+            return Err(malformed(format!("item id '{}' is used twice", item.id)));
+        }
+        match &item.effect {
+            ItemEffect::Damage { dmg_type, .. } => {
+                table
+                    .parse(dmg_type)
+                    .map_err(|e| malformed(format!("item '{}': {}", item.id, e)))?;
+            }
+            ItemEffect::Cure { statuses: ids } => {
+                if let Some(id) = unknown_status(ids, statuses) {
+                    return Err(malformed(format!(
+                        "item '{}': unknown status '{}'",
+                        item.id, id
+                    )));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(items)
+}
+
+pub fn load_skill_table(
+    asset_path: &str,
+    table: &DamageTable,
+    statuses: &StatusTable,
+) -> Result<SkillTable, LoadError> {
+    let skills: SkillTable = read_json(asset_path)?;
+    let malformed = |reason: String| LoadError::Malformed {
+        path: asset_path.to_string(),
+        reason,
+    };
+    for (i, skill) in skills.skills.iter().enumerate() {
+        if skill.id.trim().is_empty() || skill.name.trim().is_empty() {
+            return Err(malformed(format!("skill {} needs an id and a name", i)));
+        }
+        if skills.skills[..i].iter().any(|other| other.id == skill.id) {
+            return Err(malformed(format!("skill id '{}' is used twice", skill.id)));
+        }
+        if !(0.0..=1.0).contains(&skill.accuracy) {
+            return Err(malformed(format!(
+                "skill '{}': accuracy {} is outside 0.0..=1.0",
+                skill.id, skill.accuracy
+            )));
+        }
+        if skill.shape == TargetShape::User && !skill.damage.is_empty() {
+            return Err(malformed(format!(
+                "skill '{}' targets its user and must not deal damage",
+                skill.id
+            )));
+        }
+        if let Some(id) = unknown_status(&skill.statuses, statuses) {
+            return Err(malformed(format!(
+                "skill '{}': unknown status '{}'",
+                skill.id, id
+            )));
+        }
+        for component in skill.damage.iter() {
+            table
+                .parse(&component.dmg_type)
+                .map_err(|e| malformed(format!("skill '{}': {}", skill.id, e)))?;
+        }
+    }
+    Ok(skills)
+}
+
+pub fn validate_inventory(
+    asset_path: &str,
+    inventory: &Inventory,
+    items: &ItemTable,
+) -> Result<(), LoadError> {
+    match inventory.items.keys().find(|id| items.get(id).is_none()) {
+        Some(id) => Err(LoadError::Malformed {
+            path: asset_path.to_string(),
+            reason: format!("unknown item '{}'", id),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Upgrades a parsed save to `SAVE_VERSION`. The bare unit array used by
+/// `assets/players/team.json` predates save files and is wrapped into one.
+/// Saves that are objects must say which version they are.
+pub fn migrate(value: Value) -> Result<SaveFile, SaveError> {
+    let value = match value {
+        Value::Array(_) => json!({ "version": SAVE_VERSION, "party": value }),
+        _ => match value["version"].as_u64() {
+            Some(version) if version == SAVE_VERSION as u64 => value,
+            Some(version) => return Err(SaveError::UnsupportedVersion(version)),
+            None => return Err(SaveError::MissingVersion),
+        },
+    };
+    Ok(from_value(value)?)
+}
+
+pub fn read_save(path: &Path) -> Result<SaveFile, SaveError> {
+    let data = fs::read_to_string(path)?;
+    migrate(from_str(&data)?)
+}
+
+pub fn write_save(path: &Path, save: &SaveFile) -> Result<(), SaveError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(save)?)?;
+    Ok(())
 }
-fn setup() {
-    /* check for saved data */
+
+/// Copies the live combat state of a party member back into its json form.
+/// Stats are written from `base`, without what statuses do to them.
+pub fn write_unit_json(
+    unit: &mut UnitJson,
+    send: &AttackSend,
+    receive: &AttackReceive,
+    stats: &Stats,
+    table: &DamageTable,
+) {
+    let code = |t: &DamageType| table.code(t).unwrap_or(&t.0).to_string();
+    let codes = |affinity: Affinity| {
+        let mut codes: Vec<String> = receive
+            .affinities
+            .iter()
+            .filter(|(_t, a)| **a == affinity)
+            .map(|(t, _a)| code(t))
+            .collect();
+        codes.sort();
+        codes
+    };
+    unit.max_hp = receive.max_hp;
+    unit.hp = receive.hp;
+    unit.max_mp = receive.max_mp;
+    unit.mp = receive.mp;
+    unit.skills = send.skills.clone();
+    unit.dmg = stats.base.attack;
+    unit.defense = stats.base.defense;
+    unit.magic = stats.base.magic;
+    unit.speed = stats.base.speed;
+    unit.accuracy = stats.base.accuracy;
+    unit.evasion = stats.base.evasion;
+    unit.luck = stats.base.luck;
+    unit.dmg_type = code(&send.dmg_type);
+    unit.weaknesses = codes(Affinity::Weak);
+    unit.resistances = codes(Affinity::Resist);
+    unit.immunities = codes(Affinity::Immune);
+    unit.absorbs = codes(Affinity::Absorb);
+    unit.reflects = codes(Affinity::Reflect);
+}
+
+/// Members without a live entity died in the current fight.
+fn sync_party(
+    party: &mut Party,
+    members: &Query<(&PartyMember, &AttackSend, &AttackReceive, &Stats)>,
+    table: &DamageTable,
+) {
+    if members.is_empty() {
+        return;
+    }
+    let mut alive = vec![false; party.members.len()];
+    for (member, send, receive, stats) in members.iter() {
+        if let Some(unit) = party.members.get_mut(member.0) {
+            write_unit_json(unit, send, receive, stats, table);
+            alive[member.0] = true;
+        }
+    }
+    for (unit, alive) in party.members.iter_mut().zip(alive) {
+        if !alive {
+            unit.hp = 0;
+        }
+    }
+}
+
+pub fn store_party(
+    mut party: ResMut<Party>,
+    members: Query<(&PartyMember, &AttackSend, &AttackReceive, &Stats)>,
+    table: Res<DamageTable>,
+) {
+    sync_party(&mut party, &members, &table);
+}
+
+fn save_game(
+    mut requests: EventReader<SaveRequest>,
+    mut party: ResMut<Party>,
+    inventory: Res<Inventory>,
+    members: Query<(&PartyMember, &AttackSend, &AttackReceive, &Stats)>,
+    table: Res<DamageTable>,
+    slots: Res<SaveSlots>,
+    errors: Res<LoadErrors>,
+) {
+    for request in requests.iter() {
+        // Whatever stood in for what failed to load must not replace a save.
+        if !errors.0.is_empty() {
+            warn!("not saving to slot {} after a load error", request.slot);
+            continue;
+        }
+        sync_party(&mut party, &members, &table);
+        let save = SaveFile {
+            version: SAVE_VERSION,
+            party: party.members.clone(),
+            inventory: inventory.clone(),
+            gold: party.gold,
+        };
+        let path = slots.path(request.slot);
+        match write_save(&path, &save) {
+            Ok(()) => info!("saved party to {}", path.display()),
+            Err(e) => error!("could not save to {}: {}", path.display(), e),
+        }
+    }
+}
+
+fn quick_save(
+    keys: Res<Input<KeyCode>>,
+    slots: Res<SaveSlots>,
+    mut requests: EventWriter<SaveRequest>,
+) {
+    if keys.just_pressed(KeyCode::F5) {
+        requests.send(SaveRequest { slot: slots.active });
+    }
+}
+
+fn autosave(slots: Res<SaveSlots>, mut requests: EventWriter<SaveRequest>) {
+    requests.send(SaveRequest { slot: slots.active });
+}
+
+/// The active save slot, or the starting team and inventory when the slot
+/// is empty. A save that cannot be read is an error rather than a new game,
+/// so it is never overwritten.
+pub fn load_party(
+    slots: &SaveSlots,
+    table: &DamageTable,
+    items: &ItemTable,
+    skills: &SkillTable,
+) -> Result<(Party, Inventory), LoadError> {
+    let path = slots.path(slots.active);
+    if path.exists() {
+        let save = read_save(&path).map_err(|e| e.load_error(&path))?;
+        let display = path.display().to_string();
+        validate_units(&display, &save.party, table, skills)?;
+        validate_inventory(&display, &save.inventory, items)?;
+        let party = Party {
+            members: save.party,
+            gold: save.gold,
+        };
+        return Ok((party, save.inventory));
+    }
+    let members = load_units(DEFAULT_TEAM_PATH, table, skills)?;
+    let inventory = read_json(DEFAULT_INVENTORY_PATH)?;
+    validate_inventory(DEFAULT_INVENTORY_PATH, &inventory, items)?;
+    Ok((Party { members, gold: 0 }, inventory))
+}
+
+fn setup(mut commands: Commands, mut errors: ResMut<LoadErrors>) {
+    let damage_table = load_damage_table(DAMAGE_TABLE_PATH).unwrap_or_else(|e| {
+        errors.0.push(e);
+        DamageTable::default()
+    });
+    let status_table = load_status_table(STATUS_TABLE_PATH).unwrap_or_else(|e| {
+        errors.0.push(e);
+        StatusTable::default()
+    });
+    let item_table = load_item_table(ITEM_TABLE_PATH, &damage_table, &status_table)
+        .unwrap_or_else(|e| {
+            errors.0.push(e);
+            ItemTable::default()
+        });
+    let skill_table = load_skill_table(SKILL_TABLE_PATH, &damage_table, &status_table)
+        .unwrap_or_else(|e| {
+            errors.0.push(e);
+            SkillTable::default()
+        });
+    let level_curve = load_level_curve(LEVEL_CURVE_PATH).unwrap_or_else(|e| {
+        errors.0.push(e);
+        LevelCurve::default()
+    });
+    let growth_table = load_growth_table(GROWTH_TABLE_PATH).unwrap_or_else(|e| {
+        errors.0.push(e);
+        GrowthTable::default()
+    });
+    commands.insert_resource(damage_table);
+    commands.insert_resource(item_table);
+    commands.insert_resource(skill_table);
+    commands.insert_resource(status_table);
+    commands.insert_resource(level_curve);
+    commands.insert_resource(growth_table);
+    // Filled from the save slot picked on the title screen.
+    commands.insert_resource(Party::default());
+    commands.insert_resource(Inventory::default());
+}
+
+fn report_load_errors(errors: Res<LoadErrors>, mut view: ResMut<State<Views>>) {
+    if errors.0.is_empty() || *view.current() == Views::LoadError {
+        return;
+    }
+    for e in errors.0.iter() {
+        error!("{}", e);
+    }
+    let _ = view.overwrite_set(Views::LoadError);
 }
 
 impl Plugin for SaveLoadPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup);
+        app.init_resource::<SaveSlots>()
+            .init_resource::<LoadErrors>()
+            .add_event::<SaveRequest>()
+            .add_startup_system_to_stage(StartupStage::PreStartup, setup)
+            .add_system(report_load_errors)
+            .add_system(quick_save)
+            .add_system(save_game)
+            .add_system_set(SystemSet::on_enter(CombatPhases::PlayerWins).with_system(store_party))
+            // After the victory screen, so the save holds the rewards.
+            .add_system_set(SystemSet::on_exit(CombatPhases::PlayerWins).with_system(autosave))
+            .add_system_set(SystemSet::on_enter(CombatPhases::EnemyWins).with_system(store_party))
+            .add_system_set(SystemSet::on_enter(CombatPhases::Fled).with_system(store_party));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_slots(name: &str) -> SaveSlots {
+        let dir = std::env::temp_dir().join(format!("jrpg_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        SaveSlots { dir, active: 1 }
+    }
+
+    fn unit() -> Value {
+        json!({
+            "name": "Knight",
+            "sprite": "knight",
+            "max_hp": 40,
+            "hp": 25,
+            "dmg": 6,
+            "dmg_type": "slashing",
+            "weaknesses": ["fire"],
+            "resistances": []
+        })
+    }
+
+    #[test]
+    fn save_file_round_trips() {
+        let slots = temp_slots("round_trip");
+        let mut inventory = Inventory::default();
+        inventory.add("potion", 3);
+        let save = SaveFile {
+            version: SAVE_VERSION,
+            party: vec![from_value(unit()).unwrap()],
+            inventory,
+            gold: 120,
+        };
+        let path = slots.path(slots.active);
+        write_save(&path, &save).unwrap();
+        let read = read_save(&path).unwrap();
+        assert_eq!(
+            serde_json::to_value(&read).unwrap(),
+            serde_json::to_value(&save).unwrap()
+        );
+        fs::remove_dir_all(&slots.dir).unwrap();
+    }
+
+    #[test]
+    fn bare_unit_array_is_migrated() {
+        let slots = temp_slots("migrate");
+        let path = slots.path(slots.active);
+        fs::create_dir_all(&slots.dir).unwrap();
+        fs::write(&path, json!([unit()]).to_string()).unwrap();
+        let save = read_save(&path).unwrap();
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.gold, 0);
+        assert!(save.inventory.is_empty());
+        let knight = &save.party[0];
+        assert_eq!((knight.name.as_str(), knight.hp), ("Knight", 25));
+        assert_eq!((knight.max_mp, knight.mp), (0, 0));
+        assert_eq!(knight.speed, default_speed());
+        assert_eq!((knight.level, knight.xp), (default_level(), 0));
+        assert_eq!(knight.defense, 0);
+        fs::remove_dir_all(&slots.dir).unwrap();
+    }
+
+    #[test]
+    fn newer_save_is_rejected() {
+        let value = json!({ "version": SAVE_VERSION + 1, "party": [] });
+        assert!(matches!(
+            migrate(value),
+            Err(SaveError::UnsupportedVersion(v)) if v == SAVE_VERSION as u64 + 1
+        ));
+    }
+
+    #[test]
+    fn save_without_a_version_is_rejected() {
+        let value = json!({ "party": [unit()] });
+        assert!(matches!(migrate(value), Err(SaveError::MissingVersion)));
+    }
+
+    #[test]
+    fn unreadable_save_is_a_load_error() {
+        let slots = temp_slots("unreadable");
+        let path = slots.path(slots.active);
+        fs::create_dir_all(&slots.dir).unwrap();
+        fs::write(&path, "{ \"version\": 1, \"party\": [").unwrap();
+        let loaded = load_party(
+            &slots,
+            &DamageTable::default(),
+            &ItemTable::default(),
+            &SkillTable::default(),
+        );
+        assert!(matches!(loaded, Err(LoadError::Parse { .. })));
+        fs::remove_dir_all(&slots.dir).unwrap();
     }
 }
@@ -1,13 +1,23 @@
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
 pub enum Views {
+    Title,
+    Overworld,
     Combat,
+    LoadError,
 }
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
 pub enum CombatPhases {
+    Inactive,
+    Loading,
+    Opening,
     SelectActive,
     SelectAction,
+    SelectItem,
+    SelectSkill,
     SelectTarget,
     Enemy,
     EnemyWins,
     PlayerWins,
+    /// The party ran from a fight that allows it.
+    Fled,
 }